@@ -1,15 +1,29 @@
+use std::time::Duration;
+
 use bevy::{
+    app::ScheduleRunnerPlugin,
     prelude::*,
     window::{
-        ApplicationLifetime, RequestRedraw, WindowBackendScaleFactorChanged, WindowCloseRequested,
-        WindowClosed, WindowCreated, WindowDestroyed, WindowFocused, WindowResized,
-        WindowScaleFactorChanged, WindowThemeChanged,
+        ApplicationLifetime, PrimaryWindow, RequestRedraw, WindowBackendScaleFactorChanged,
+        WindowCloseRequested, WindowClosed, WindowCreated, WindowDestroyed, WindowFocused,
+        WindowResized, WindowScaleFactorChanged, WindowThemeChanged,
     },
+    winit::WinitPlugin,
 };
 
-/// A plugin that adds all the default plugins, except for the `WindowPlugin`.
+/// Marks a primary window that only exists as an entity, with no OS window behind it.
+#[derive(Debug, Component)]
+pub(crate) struct VirtualWindow;
+
+/// A plugin that adds all the default plugins, except for the `WindowPlugin` and `WinitPlugin`.
 /// it adds the necessary elements for the rest of the plugins to work.
 ///
+/// Instead of a real window, a virtual primary window entity is spawned. It has a size, scale factor and
+/// cursor position like a normal window, so mouse driven tests can be played back, but nothing is shown on screen.
+///
+/// Without a window there is no vsync to pace the updates, so they run on the default fixed timestep instead of
+/// spinning a core. This also caps playback with `PlaybackTestingOptions::fixed_timestep` to 64 updates a second.
+///
 /// **IMPORTANT**: Bevy cannot take screenshots without a window, so this plugin prevents
 /// playback test gear from taking screenshots.
 ///
@@ -37,6 +51,18 @@ impl Plugin for HeadlessDefaultPlugins {
             .add_event::<WindowMoved>()
             .add_event::<WindowThemeChanged>()
             .add_event::<ApplicationLifetime>()
-            .add_plugins(DefaultPlugins.build().disable::<WindowPlugin>());
+            .add_plugins(
+                DefaultPlugins
+                    .build()
+                    .disable::<WindowPlugin>()
+                    // Winit would open a real window for the virtual one
+                    .disable::<WinitPlugin>()
+                    .add(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                        1.0 / 64.0,
+                    ))),
+            );
+
+        app.world
+            .spawn((Window::default(), PrimaryWindow, VirtualWindow));
     }
 }
@@ -1,46 +1,300 @@
 use std::{
-    fs::read_to_string,
+    env, fmt,
+    fs::{create_dir_all, read, write, File},
+    io,
     path::{Path, PathBuf},
+    str::FromStr,
     time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
 
-use bevy::{input::mouse::MouseWheel, prelude::*};
+use bevy::{
+    core::FrameCount,
+    input::{
+        keyboard::Key,
+        mouse::MouseWheel,
+        touch::{ForceTouch, TouchPhase},
+    },
+    prelude::*,
+};
 
 use crate::TestWrangler;
 
 mod artefact_paths;
+mod assertions;
+mod binary_format;
+mod controls;
+mod diffing;
+mod editing;
+mod expectations;
+mod extending;
 mod frame_metrics;
+mod ids;
+mod injection;
+mod invariants;
+mod markers;
 mod playback;
 mod recording;
+mod results;
+mod text_format;
+mod versioning;
+
+pub(crate) use artefact_paths::ArtefactPaths;
+pub use controls::RecordingControls;
+pub use diffing::{InputChange, ScriptDiff};
+pub use expectations::ExpectationAppExt;
+pub use extending::ExtendFrom;
+pub use invariants::InvariantAppExt;
+pub(crate) use results::ResultWriter;
 
 #[derive(Debug, Resource)]
-struct StartTime(Duration);
-#[derive(Debug, Clone, Copy, Event)]
-struct TestQuitEvent(bool);
+struct StartTime {
+    time: Duration,
+    frame: u32,
+}
+
+impl StartTime {
+    fn timestamp(
+        &self,
+        time: &Time<Real>,
+        frame_count: &FrameCount,
+        frame_indexed: bool,
+    ) -> Timestamp {
+        if frame_indexed {
+            Timestamp::Frame(frame_count.0.wrapping_sub(self.frame))
+        } else {
+            Timestamp::Time(time.elapsed() - self.time)
+        }
+    }
+
+    /// How far into the script playback is, sped up or slowed down by the playback rate.
+    fn script_position(
+        &self,
+        time: &Time<Real>,
+        frame_count: &FrameCount,
+        playback_rate: f32,
+    ) -> (Duration, u32) {
+        // Instead of stretching every timestamp, how far into the script playback is gets scaled
+        let elapsed = (time.elapsed() - self.time).mul_f32(playback_rate);
+        let frames = frame_count.0.wrapping_sub(self.frame) as f32 * playback_rate;
+        (elapsed, frames as u32)
+    }
+}
+
+/// How a playback test ended, and why.
+#[derive(Debug, Clone, Event)]
+struct TestQuitEvent {
+    outcome: Outcome,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Passed,
+    Failed,
+    // Nothing passed or failed the test before the assert window ran out
+    TimedOut,
+}
+
+impl TestQuitEvent {
+    fn new(outcome: Outcome, reason: Option<String>) -> Self {
+        Self { outcome, reason }
+    }
+
+    fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+impl fmt::Display for TestQuitEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.outcome {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::TimedOut => "timed out",
+        };
+
+        match &self.reason {
+            Some(reason) => write!(f, "{}: {}", outcome, reason),
+            None => write!(f, "{}", outcome),
+        }
+    }
+}
+
+/// The inputs of a test case, as recorded or played back.
+/// Existing scripts can be read, edited and saved again with its methods, which is what the `bitt` command line tool does.
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Resource)]
-struct TestScript {
-    events: Vec<(Duration, UserInput)>,
+pub struct TestScript {
+    events: Vec<(Timestamp, UserInput)>,
+    // Recorded with `PlaybackTestingOptions::expect_failure`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    expect_failure: bool,
+}
+
+/// When an input happened, counted from the start of the test.
+/// Written and parsed like `1.250s` for times and `75f` for frames.
+/// A script uses one or the other, and times sort before frames.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum Timestamp {
+    /// Time since the start.
+    Time(Duration),
+    /// Frames since the start, in frame indexed scripts.
+    Frame(u32),
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&text_format::write_timestamp(self))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        text_format::parse_timestamp(s)
+    }
+}
+
+impl Timestamp {
+    fn reached(&self, elapsed: Duration, frames: u32) -> bool {
+        match self {
+            Timestamp::Time(time) => *time <= elapsed,
+            Timestamp::Frame(frame) => *frame <= frames,
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestScript {
+    // One of most kinds of input, with strings that need quoting and a timestamp that needs every digit
+    fn example() -> Self {
+        let pad = Gamepad::new(1);
+        let inputs = vec![
+            UserInput::ControllerConnect(pad, "Pad \"Pro\" 2".to_string()),
+            UserInput::LogicalKeyPress(KeyCode::KeyA, Key::Character("a".into())),
+            UserInput::LogicalKeyRelease(KeyCode::KeyA, Key::Character("a".into())),
+            UserInput::LogicalKeyPress(KeyCode::Quote, Key::Character("\"".into())),
+            UserInput::LogicalKeyPress(KeyCode::Enter, Key::Enter),
+            UserInput::KeyPress(KeyCode::Digit1),
+            UserInput::KeyRelese(KeyCode::Digit1),
+            UserInput::Character("say \"hi\" \\ bye".to_string()),
+            UserInput::MouseButtonPress(MouseButton::Left),
+            UserInput::MouseMove(Vec2::new(1.5, -2.0), Some(Vec2::new(100.0, 50.25))),
+            UserInput::MouseMove(Vec2::new(0.1, 0.2), None),
+            UserInput::MouseButtonRelease(MouseButton::Other(7)),
+            UserInput::MouseScroll(MouseWheel {
+                unit: bevy::input::mouse::MouseScrollUnit::Pixel,
+                x: 0.0,
+                y: -12.5,
+                window: Entity::PLACEHOLDER,
+            }),
+            UserInput::Touch(
+                TouchPhase::Moved,
+                3,
+                Vec2::new(4.0, 8.0),
+                Some(ForceTouch::Normalized(0.5)),
+            ),
+            UserInput::ImeEnabled,
+            UserInput::ImePreedit("日本".to_string(), Some((0, 3))),
+            UserInput::ImeCommit("日本語".to_string()),
+            UserInput::ImeDisabled,
+            UserInput::ControllerButtonPress(GamepadButton::new(pad, GamepadButtonType::South)),
+            UserInput::ControllerButtonChange(
+                GamepadButton::new(pad, GamepadButtonType::RightTrigger2),
+                0.75,
+            ),
+            UserInput::ControllerAxisChange(
+                GamepadAxis::new(pad, GamepadAxisType::LeftStickX),
+                -0.5,
+            ),
+            UserInput::ControllerDisconnect(pad),
+            UserInput::Marker("boss spawned".to_string()),
+            UserInput::Quit,
+        ];
+
+        let events = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let time = Duration::from_millis(250 * index as u64) + Duration::from_nanos(1);
+                (Timestamp::Time(time), input)
+            })
+            .collect();
+
+        Self {
+            events,
+            expect_failure: false,
+        }
+    }
+
+    fn with_frames(mut self) -> Self {
+        for (index, (timestamp, _)) in self.events.iter_mut().enumerate() {
+            *timestamp = Timestamp::Frame(3 * index as u32);
+        }
+        self
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Bevy types are saved through `ids`, so the scripts stay the same when bevy renames things
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 enum UserInput {
-    KeyPress(KeyCode),
-    KeyRelese(KeyCode),
-    MouseButtonPress(MouseButton),
-    MouseButtonRelease(MouseButton),
-    ControllerAxisChange(GamepadAxis, f32),
-    ControllerButtonPress(GamepadButton),
-    ControllerButtonRelease(GamepadButton),
-    MouseScroll(MouseWheel),
+    // Only kept for scripts recorded before logical keys were
+    KeyPress(#[serde(with = "ids::stable_id")] KeyCode),
+    KeyRelese(#[serde(with = "ids::stable_id")] KeyCode),
+    MouseButtonPress(#[serde(with = "ids::stable_id")] MouseButton),
+    MouseButtonRelease(#[serde(with = "ids::stable_id")] MouseButton),
+    ControllerAxisChange(#[serde(with = "ids::gamepad_axis")] GamepadAxis, f32),
+    // Press and release are only kept for scripts recorded before analog values were
+    ControllerButtonPress(#[serde(with = "ids::gamepad_button")] GamepadButton),
+    ControllerButtonRelease(#[serde(with = "ids::gamepad_button")] GamepadButton),
+    ControllerButtonChange(#[serde(with = "ids::gamepad_button")] GamepadButton, f32),
+    MouseScroll(#[serde(with = "ids::mouse_wheel")] MouseWheel),
     MouseMove(Vec2, Option<Vec2>),
+    LogicalKeyPress(
+        #[serde(with = "ids::stable_id")] KeyCode,
+        #[serde(with = "ids::stable_id")] Key,
+    ),
+    LogicalKeyRelease(
+        #[serde(with = "ids::stable_id")] KeyCode,
+        #[serde(with = "ids::stable_id")] Key,
+    ),
+    Character(String),
+    ImePreedit(String, Option<(usize, usize)>),
+    ImeCommit(String),
+    ImeEnabled,
+    ImeDisabled,
+    Touch(
+        #[serde(with = "ids::stable_id")] TouchPhase,
+        u64,
+        Vec2,
+        Option<ForceTouch>,
+    ),
+    ControllerConnect(#[serde(with = "ids::gamepad")] Gamepad, String),
+    ControllerDisconnect(#[serde(with = "ids::gamepad")] Gamepad),
+    // Not an input, but where the game called `TestWrangler::mark`
+    Marker(String),
     Quit,
 }
 
+/// How new recordings are written to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum ScriptFormat {
+    /// Compact, but everything is on one line.
+    #[default]
+    Json,
+    /// One input per line, like `0.500s press KeyA "a"`. Easy to review and edit by hand.
+    Text,
+    /// Compressed binary, many times smaller than the others. Good for long recordings with a lot of mouse movement.
+    Binary,
+}
+
 /// Options to use when running playback testing.
 /// Inserted as a resource for test gear usage, you shouldn't modify it
-#[derive(Debug, Resource, Clone)]
+#[derive(Debug, Resource, Clone, Serialize)]
 pub struct PlaybackTestingOptions {
     /// If true, the test will panic if the script doesn't exist.
     pub read_only: bool,
@@ -54,6 +308,42 @@ pub struct PlaybackTestingOptions {
     /// This takes more time on less powerful hardware, for example in CI, so if that is a problem you can
     /// set this to false and manually call `TestWrangler::start` through the TestWrangler resource.
     pub manual_start: bool,
+    /// If true, input from real devices is ignored during playback, so only the script can control the game.
+    /// Touching the mouse or a controller while a test is running won't affect the result.
+    /// Bevy can't drop only the device events, so in a frame where a device did send something,
+    /// scripted input from the previous frame that `FixedUpdate` systems haven't read yet is dropped too.
+    pub isolate_input: bool,
+    /// If true, new recordings stamp inputs with the frame they happened on instead of the time.
+    /// Such scripts are replayed on exactly the same frames regardless of how long the frames take,
+    /// which makes them more reliable on slow machines, provided the game itself doesn't depend on frame times.
+    /// Has no effect on playback, the timing is decided by the script.
+    pub frame_indexed: bool,
+    /// If set, playback advances time by this many seconds every update instead of following the clock,
+    /// and the app runs as fast as it can. A long script can then be replayed in a fraction of the time it took to record.
+    /// Everything reading `Time`, including the frame metrics, will see the fixed step instead of the real frame time.
+    pub fixed_timestep: Option<f32>,
+    /// The format new recordings are saved in. Scripts in either format can be played back.
+    pub script_format: ScriptFormat,
+    /// How fast the script is played back, 0.5 plays the inputs at half speed and 2.0 at double speed.
+    /// Only the inputs are stretched, the game runs as usual, so this checks whether it depends on human-speed timing.
+    /// The assert window is stretched along with the inputs.
+    pub playback_rate: f32,
+    /// How many seconds earlier or later than in the recording a marker can be reached during playback.
+    /// See `TestWrangler::mark`.
+    pub marker_tolerance: f32,
+    /// If set and the script exists, it is replayed up to this point, after which the game is yours to control.
+    /// Your inputs are recorded after the replayed ones, and saved as the new script once the test passes again.
+    /// Real input isn't ignored during the replay, so don't touch the controls before it says it's done.
+    pub extend: Option<ExtendFrom>,
+    /// If true, the test is recorded even if the script exists. The old script is moved next to it with a `.bak`
+    /// extension once the new recording passes, and kept as is if it doesn't.
+    /// Setting the `BITT_RERECORD` env var does the same, without having to change the code.
+    pub rerecord: bool,
+    /// Hotkeys for pausing, saving or discarding a recording while it is going, and whether to show if it's on.
+    pub recording_controls: RecordingControls,
+    /// If true, new recordings are saved when the test fails instead of when it passes, for capturing something the
+    /// game should prevent. The script remembers this, and passes in playback only if the test fails again.
+    pub expect_failure: bool,
 }
 
 impl Default for PlaybackTestingOptions {
@@ -63,6 +353,16 @@ impl Default for PlaybackTestingOptions {
             assert_window: 5.0,
             collect_frame_metrics: true,
             manual_start: false,
+            isolate_input: true,
+            frame_indexed: false,
+            fixed_timestep: None,
+            script_format: ScriptFormat::default(),
+            playback_rate: 1.0,
+            marker_tolerance: 1.0,
+            extend: None,
+            rerecord: false,
+            recording_controls: RecordingControls::default(),
+            expect_failure: false,
         }
     }
 }
@@ -90,26 +390,81 @@ impl Plugin for PlaybackTestGear {
     fn build(&self, app: &mut App) {
         let (script_path, artefact_path) = get_paths(self.case_name.clone());
 
-        if let Some(script) = load_script(&script_path) {
-            if self.options.collect_frame_metrics {
-                app.add_plugins(frame_metrics::FrameMetricPlugin);
-            }
+        assert!(
+            self.options.playback_rate > 0.0,
+            "Playback rate must be positive, got {}",
+            self.options.playback_rate
+        );
 
-            app.add_plugins(playback::PlaybackPlugin {
-                script,
-                artefact_path,
-            })
+        let mut options = self.options.clone();
+        options.rerecord |= env::var_os("BITT_RERECORD").is_some();
+        assert!(
+            !(options.rerecord && options.read_only),
+            "Can't re-record {} in read only mode",
+            self.case_name
+        );
+
+        let script = if options.rerecord {
+            None
         } else {
-            assert!(
-                !self.options.read_only,
-                "Script {} doesn't exist",
-                self.case_name
-            );
+            load_script(&script_path)
+        };
+
+        // Extending records as well, after replaying the start of the script
+        let mode = match (&script, &self.options.extend) {
+            (Some(_), None) => results::Mode::Playback,
+            _ => results::Mode::Record,
+        };
+        app.add_plugins(results::ResultPlugin {
+            case_name: self.case_name.clone(),
+            mode,
+            script_path: script_path.clone(),
+            artefact_path: artefact_path.clone(),
+        });
+
+        match (script, &self.options.extend) {
+            (Some(script), Some(from)) => {
+                let (script, handover) = extending::replayed_part(&script, from)
+                    .unwrap_or_else(|err| panic!("Can't extend {}: {}", self.case_name, err));
+                // New inputs have to be stamped the same way as the replayed ones
+                options.frame_indexed = matches!(handover, Timestamp::Frame(_));
+                options.expect_failure = script.expect_failure;
 
-            app.add_plugins(recording::RecordingPlugin { script_path })
+                app.add_plugins(recording::RecordingPlugin {
+                    script_path,
+                    script: script.clone(),
+                })
+                .add_plugins(extending::ExtendPlugin { script, handover })
+            }
+            (Some(script), None) => {
+                if self.options.collect_frame_metrics {
+                    app.add_plugins(frame_metrics::FrameMetricPlugin);
+                }
+
+                app.add_plugins(playback::PlaybackPlugin {
+                    script,
+                    artefact_path,
+                    fixed_timestep: self.options.fixed_timestep,
+                })
+            }
+            (None, _) => {
+                assert!(
+                    !self.options.read_only,
+                    "Script {} doesn't exist",
+                    self.case_name
+                );
+
+                app.add_plugins(recording::RecordingPlugin {
+                    script_path,
+                    script: TestScript::default(),
+                })
+            }
         }
-        .insert_resource(self.options.clone())
+        .insert_resource(options)
         .init_resource::<TestWrangler>();
+        app.world.resource_mut::<TestWrangler>().collect_marks = true;
+
+        app.add_systems(Last, assertions::stamp_assertions);
 
         if self.options.manual_start {
             app.add_systems(First, set_start_time_manual);
@@ -122,21 +477,29 @@ impl Plugin for PlaybackTestGear {
 fn set_start_time_automatic(
     mut commands: Commands,
     time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
     start_time: Option<Res<StartTime>>,
 ) {
     if start_time.is_none() {
-        commands.insert_resource(StartTime(time.elapsed()));
+        commands.insert_resource(StartTime {
+            time: time.elapsed(),
+            frame: frame_count.0,
+        });
     }
 }
 
 fn set_start_time_manual(
     mut commands: Commands,
     time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
     start_time: Option<Res<StartTime>>,
     wrangler: Res<TestWrangler>,
 ) {
     if start_time.is_none() && wrangler.started {
-        commands.insert_resource(StartTime(time.elapsed()));
+        commands.insert_resource(StartTime {
+            time: time.elapsed(),
+            frame: frame_count.0,
+        });
     }
 }
 
@@ -154,10 +517,71 @@ fn get_paths(case_name: String) -> (PathBuf, PathBuf) {
 
 fn load_script(path: &Path) -> Option<TestScript> {
     if path.exists() {
-        let script = read_to_string(path).unwrap();
-        let script: TestScript = serde_json::from_str(&script).unwrap();
+        let (script, _) = read_script(path)
+            .unwrap_or_else(|err| panic!("Failed to load {}: {}", path.display(), err));
         Some(script)
     } else {
         None
     }
 }
+
+fn read_script(path: &Path) -> Result<(TestScript, ScriptFormat), String> {
+    let script = read(path).map_err(|err| err.to_string())?;
+
+    // Binary scripts start with a magic string, json ones are a single object, and text ones start with
+    // a header, a timestamp or a comment
+    let (format, (header, script)) = if script.starts_with(binary_format::MAGIC) {
+        let script = binary_format::from_binary(&script)?;
+        (ScriptFormat::Binary, versioning::load(script)?)
+    } else {
+        let script = String::from_utf8(script).map_err(|err| err.to_string())?;
+        if script.trim_start().starts_with('{') {
+            let script = serde_json::from_str(&script).map_err(|err| err.to_string())?;
+            (ScriptFormat::Json, versioning::load(script)?)
+        } else {
+            let script = text_format::from_text(&script).map_err(|err| err.to_string())?;
+            (ScriptFormat::Text, script)
+        }
+    };
+
+    if let Some(bevy) = header.bevy.filter(|bevy| bevy != versioning::BEVY_VERSION) {
+        warn!(
+            "{} was recorded with bevy {}, but bitt is built for {}. Re-record it if playback doesn't go as expected.",
+            path.display(),
+            bevy,
+            versioning::BEVY_VERSION
+        );
+    }
+
+    Ok((script, format))
+}
+
+fn write_script(path: &Path, script: &TestScript, format: ScriptFormat) -> io::Result<()> {
+    if let Some(prefix) = path.parent() {
+        create_dir_all(prefix)?;
+    }
+
+    match format {
+        ScriptFormat::Json => {
+            let file = File::create(path)?;
+            let script = versioning::VersionedScript {
+                header: versioning::ScriptHeader::current(),
+                script,
+            };
+            serde_json::to_writer(file, &script)?;
+        }
+        ScriptFormat::Text => write(path, text_format::to_text(script))?,
+        ScriptFormat::Binary => write(path, binary_format::to_binary(script))?,
+    }
+
+    Ok(())
+}
+
+/// Rewrites the script at `from` to `to` in the given format, for example to turn a long json recording into a
+/// smaller binary one. Reads scripts in any format, and `from` and `to` can be the same file.
+/// Panics if the script can't be read or written.
+pub fn convert_script(from: &Path, to: &Path, format: ScriptFormat) {
+    let script =
+        load_script(from).unwrap_or_else(|| panic!("Script {} doesn't exist", from.display()));
+    write_script(to, &script, format).unwrap();
+}
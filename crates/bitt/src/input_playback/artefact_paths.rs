@@ -16,10 +16,26 @@ impl ArtefactPaths {
         self.base.join("post-assert.png")
     }
 
+    pub fn violation_screenshot(&self) -> PathBuf {
+        self.base.join("violation.png")
+    }
+
     pub fn frame_metrics(&self) -> PathBuf {
         self.base.join("frame_metrics.json")
     }
 
+    pub fn outcome(&self) -> PathBuf {
+        self.base.join("outcome.txt")
+    }
+
+    pub fn assertions(&self) -> PathBuf {
+        self.base.join("assertions.txt")
+    }
+
+    pub fn markers(&self) -> PathBuf {
+        self.base.join("markers.txt")
+    }
+
     pub fn saved(&self) -> bool {
         (self.running_headless
             || (Self::file_saved(self.pre_assert_screenshot())
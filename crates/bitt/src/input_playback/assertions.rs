@@ -0,0 +1,67 @@
+use std::{fs, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::TestWrangler;
+
+use super::{artefact_paths::ArtefactPaths, StartTime, Timestamp};
+
+/// Notes down when each named assertion was resolved.
+pub(super) fn stamp_assertions(
+    mut wrangler: ResMut<TestWrangler>,
+    time: Res<Time<Real>>,
+    start_time: Option<Res<StartTime>>,
+) {
+    // Checked first, so that change detection isn't set off every frame
+    if !wrangler
+        .assertions
+        .iter()
+        .any(|assertion| assertion.outcome.is_some() && assertion.resolved_at.is_none())
+    {
+        return;
+    }
+
+    let now = start_time.map_or(Duration::ZERO, |start_time| {
+        time.elapsed() - start_time.time
+    });
+    for assertion in &mut wrangler.assertions {
+        if assertion.outcome.is_some() && assertion.resolved_at.is_none() {
+            assertion.resolved_at = Some(now);
+        }
+    }
+}
+
+pub(super) fn write_assertion_report(wrangler: Res<TestWrangler>, path: Res<ArtefactPaths>) {
+    if wrangler.assertions.is_empty() {
+        return;
+    }
+
+    let report: String = wrangler
+        .assertions
+        .iter()
+        .map(|assertion| {
+            let status = match assertion.outcome {
+                Some(true) => "passed",
+                Some(false) => "failed",
+                None => "pending",
+            };
+            let resolved_at = assertion
+                .resolved_at
+                .map(|resolved_at| Timestamp::Time(resolved_at).to_string())
+                .unwrap_or_default();
+            let reason = assertion
+                .reason
+                .as_ref()
+                .map(|reason| format!(", {}", reason))
+                .unwrap_or_default();
+
+            format!(
+                "{:<8}{:>12}  {}{}\n",
+                status, resolved_at, assertion.name, reason
+            )
+        })
+        .collect();
+
+    println!("Assertions:\n{}", report.trim_end());
+    fs::write(path.assertions(), report).unwrap();
+}
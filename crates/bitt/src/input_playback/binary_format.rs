@@ -0,0 +1,170 @@
+use std::{io::Write, time::Duration};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{versioning::ScriptHeader, TestScript, Timestamp, UserInput};
+
+/// Binary scripts start with this, so they can be told apart from json and text.
+pub(super) const MAGIC: &[u8] = b"bitt-script\0";
+
+#[derive(Serialize, Deserialize)]
+struct BinaryScript<Input> {
+    header: ScriptHeader,
+    events: Vec<(Delta, Input)>,
+    #[serde(default)]
+    expect_failure: bool,
+}
+
+/// How long after the previous input of the same kind an input happened.
+#[derive(Serialize, Deserialize)]
+enum Delta {
+    Nanos(u64),
+    Frames(u32),
+}
+
+/// Message pack with the timestamps as deltas, compressed with zlib.
+pub(super) fn to_binary(script: &TestScript) -> Vec<u8> {
+    let mut previous_time = Duration::ZERO;
+    let mut previous_frame = 0;
+
+    // Timestamps out of order are clamped to the previous one, which is also when playback would use them
+    let events = script
+        .events
+        .iter()
+        .map(|(timestamp, input)| {
+            let delta = match *timestamp {
+                Timestamp::Time(time) => {
+                    let delta = time.saturating_sub(previous_time);
+                    previous_time += delta;
+                    Delta::Nanos(delta.as_nanos() as u64)
+                }
+                Timestamp::Frame(frame) => {
+                    let delta = frame.saturating_sub(previous_frame);
+                    previous_frame += delta;
+                    Delta::Frames(delta)
+                }
+            };
+            (delta, input)
+        })
+        .collect();
+
+    let script: BinaryScript<&UserInput> = BinaryScript {
+        header: ScriptHeader::current(),
+        events,
+        expect_failure: script.expect_failure,
+    };
+
+    // Named, so the inputs decode to the same json the other formats use
+    let encoded = rmp_serde::to_vec_named(&script).unwrap();
+    let mut encoder = ZlibEncoder::new(MAGIC.to_vec(), Compression::best());
+    encoder.write_all(&encoded).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decodes the script into the json form, so it can be migrated like one.
+pub(super) fn from_binary(bytes: &[u8]) -> Result<Value, String> {
+    let compressed = bytes
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| "not a binary script".to_string())?;
+
+    let script: BinaryScript<Value> = rmp_serde::from_read(ZlibDecoder::new(compressed))
+        .map_err(|err| format!("corrupted binary script: {}", err))?;
+    script.header.check_supported()?;
+
+    let mut time = Duration::ZERO;
+    let mut frame = 0u32;
+    let events: Vec<Value> = script
+        .events
+        .into_iter()
+        .map(|(delta, input)| {
+            let timestamp = match delta {
+                Delta::Nanos(nanos) => {
+                    time = time.saturating_add(Duration::from_nanos(nanos));
+                    Timestamp::Time(time)
+                }
+                Delta::Frames(frames) => {
+                    frame = frame.saturating_add(frames);
+                    Timestamp::Frame(frame)
+                }
+            };
+            json!([timestamp, input])
+        })
+        .collect();
+
+    Ok(json!({
+        "version": script.header.version,
+        "bevy": script.header.bevy,
+        "events": events,
+        "expect_failure": script.expect_failure,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_playback::versioning::{self, SCRIPT_VERSION};
+
+    fn round_trip(script: &TestScript) -> TestScript {
+        let bytes = to_binary(script);
+        assert!(bytes.starts_with(MAGIC));
+
+        let (header, loaded) = versioning::load(from_binary(&bytes).unwrap()).unwrap();
+        assert_eq!(header.version, SCRIPT_VERSION);
+        loaded
+    }
+
+    #[test]
+    fn round_trips_every_input() {
+        let script = TestScript::example();
+        let loaded = round_trip(&script);
+
+        assert_eq!(loaded.events, script.events);
+        assert!(!loaded.expect_failure);
+    }
+
+    #[test]
+    fn round_trips_frames_and_expected_failures() {
+        let mut script = TestScript::example().with_frames();
+        script.expect_failure = true;
+        let loaded = round_trip(&script);
+
+        assert_eq!(loaded.events, script.events);
+        assert!(loaded.expect_failure);
+    }
+
+    #[test]
+    fn clamps_timestamps_out_of_order() {
+        let time = |millis| Timestamp::Time(Duration::from_millis(millis));
+        let script = TestScript {
+            events: vec![
+                (time(500), UserInput::ImeEnabled),
+                (time(200), UserInput::ImeDisabled),
+                (time(700), UserInput::Quit),
+            ],
+            expect_failure: false,
+        };
+
+        let timestamps: Vec<_> = round_trip(&script)
+            .events
+            .into_iter()
+            .map(|(timestamp, _)| timestamp)
+            .collect();
+        assert_eq!(timestamps, [time(500), time(500), time(700)]);
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert_eq!(
+            from_binary(b"{\"events\": []}").unwrap_err(),
+            "not a binary script"
+        );
+
+        let mut bytes = to_binary(&TestScript::example());
+        bytes.truncate(bytes.len() / 2);
+        assert!(from_binary(&bytes)
+            .unwrap_err()
+            .starts_with("corrupted binary script"));
+    }
+}
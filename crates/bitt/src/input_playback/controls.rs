@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use bevy::{app::AppExit, core::FrameCount, prelude::*};
+use serde::Serialize;
+
+use crate::PlaybackTestingOptions;
+
+use super::{extending::Replay, StartTime};
+
+/// Keys for controlling a recording while it is going, see `PlaybackTestingOptions::recording_controls`.
+/// The presses of these keys are left out of the script.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordingControls {
+    /// Pauses the recording, or resumes it if it was paused. The time spent paused is cut out of the script.
+    pub pause: Option<KeyCode>,
+    /// Saves the recording and quits right away, without waiting for the test to pass.
+    pub save: Option<KeyCode>,
+    /// Quits without saving anything.
+    pub discard: Option<KeyCode>,
+    /// If true, a small text in the corner of the window shows whether the recording is on.
+    /// Off by default, as the text is a UI entity that the game's own queries and UI layout would see while recording,
+    /// but not during playback.
+    pub indicator: bool,
+}
+
+impl Default for RecordingControls {
+    fn default() -> Self {
+        Self {
+            pause: Some(KeyCode::F9),
+            save: Some(KeyCode::F10),
+            discard: Some(KeyCode::F11),
+            indicator: false,
+        }
+    }
+}
+
+impl RecordingControls {
+    pub(super) fn is_hotkey(&self, key: KeyCode) -> bool {
+        [self.pause, self.save, self.discard].contains(&Some(key))
+    }
+}
+
+/// When the recording was paused, in real time and frames.
+#[derive(Debug, Default, Resource)]
+pub(super) struct RecordingPause(Option<(Duration, u32)>);
+
+impl RecordingPause {
+    pub(super) fn is_paused(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Sent to save the script and quit.
+#[derive(Debug, Clone, Copy, Event)]
+pub(super) struct SaveQuitEvent;
+
+#[derive(Debug, Component)]
+pub(super) struct RecordingIndicator;
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn recording_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    options: Res<PlaybackTestingOptions>,
+    mut pause: ResMut<RecordingPause>,
+    start_time: Option<ResMut<StartTime>>,
+    replay: Option<Res<Replay>>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    mut save_events: EventWriter<SaveQuitEvent>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    let Some(mut start_time) = start_time else {
+        return;
+    };
+    if replay.is_some_and(|replay| !replay.done) {
+        return;
+    }
+
+    let controls = &options.recording_controls;
+    let pressed = |key: Option<KeyCode>| key.is_some_and(|key| keys.just_pressed(key));
+
+    if pressed(controls.discard) {
+        println!("Recording discarded, nothing was saved");
+        exit_events.send(AppExit);
+        return;
+    }
+
+    let save = pressed(controls.save);
+    if pressed(controls.pause) || (save && pause.is_paused()) {
+        if let Some((time_paused, frame_paused)) = pause.0.take() {
+            // Moving the start forward leaves the pause out of the timestamps
+            start_time.time += time.elapsed() - time_paused;
+            start_time.frame = start_time
+                .frame
+                .wrapping_add(frame_count.0.wrapping_sub(frame_paused));
+            println!("Recording resumed");
+        } else {
+            pause.0 = Some((time.elapsed(), frame_count.0));
+            println!("Recording paused");
+        }
+    }
+
+    if save {
+        save_events.send(SaveQuitEvent);
+    }
+}
+
+pub(super) fn spawn_indicator(mut commands: Commands, options: Res<PlaybackTestingOptions>) {
+    if !options.recording_controls.indicator {
+        return;
+    }
+
+    commands.spawn((
+        TextBundle {
+            z_index: ZIndex::Global(i32::MAX),
+            ..TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 20.0,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                right: Val::Px(8.0),
+                ..default()
+            })
+        },
+        RecordingIndicator,
+    ));
+}
+
+pub(super) fn update_indicator(
+    mut indicator_query: Query<&mut Text, With<RecordingIndicator>>,
+    pause: Res<RecordingPause>,
+    start_time: Option<Res<StartTime>>,
+    replay: Option<Res<Replay>>,
+) {
+    let (state, color) = if start_time.is_none() {
+        ("", Color::NONE)
+    } else if replay.is_some_and(|replay| !replay.done) {
+        ("REPLAYING", Color::WHITE)
+    } else if pause.is_paused() {
+        ("PAUSED", Color::YELLOW)
+    } else {
+        ("REC", Color::RED)
+    };
+
+    for mut text in &mut indicator_query {
+        // Only written when it changes, to not trigger a relayout every frame
+        if text.sections[0].value != state {
+            text.sections[0].value = state.to_string();
+            text.sections[0].style.color = color;
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::{collections::HashMap, fmt};
+
+use bevy::math::Vec2;
+
+use super::{editing::minus, text_format::write_input, TestScript, Timestamp, UserInput};
+
+/// How an input differs between two scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputChange {
+    /// The input is only in the new script.
+    Added {
+        /// When it happens in the new script.
+        at: Timestamp,
+        /// The input, written like in text scripts.
+        input: String,
+    },
+    /// The input is only in the old script.
+    Removed {
+        /// When it happened in the old script.
+        at: Timestamp,
+        /// The input, written like in text scripts.
+        input: String,
+    },
+    /// The input is in both, but at different times.
+    Retimed {
+        /// When it happened in the old script.
+        from: Timestamp,
+        /// When it happens in the new script.
+        to: Timestamp,
+        /// The input, written like in text scripts.
+        input: String,
+    },
+}
+
+impl fmt::Display for InputChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputChange::Added { at, input } => write!(f, "+ {:>12}  {}", at, input),
+            InputChange::Removed { at, input } => write!(f, "- {:>12}  {}", at, input),
+            InputChange::Retimed { from, to, input } => {
+                write!(f, "~ {:>12}  {} (was at {})", to, input, from)
+            }
+        }
+    }
+}
+
+/// The differences between two scripts, in the order they happen.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptDiff {
+    /// Every input that was added, removed or moved.
+    pub changes: Vec<InputChange>,
+}
+
+impl ScriptDiff {
+    /// True if the scripts have the same inputs at about the same times.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for ScriptDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+enum Step {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+impl TestScript {
+    /// Lines up the inputs of the two scripts, and reports the ones that were added, removed, or moved by more than
+    /// `tolerance`. Mouse movement, touches and analog values match others on the same button or axis if they are
+    /// within a few pixels or a tenth of the range of each other, other inputs only match if they are the same.
+    pub fn diff(&self, new: &TestScript, tolerance: Timestamp) -> Result<ScriptDiff, String> {
+        self.check_kind(tolerance)?;
+        new.check_kind(tolerance)?;
+
+        let changes = align(&self.events, &new.events)
+            .into_iter()
+            .filter_map(|step| match step {
+                Step::Same(old_index, new_index) => {
+                    let (from, input) = &self.events[old_index];
+                    let (to, _) = &new.events[new_index];
+                    let difference = if from < to {
+                        minus(*to, *from)
+                    } else {
+                        minus(*from, *to)
+                    };
+
+                    (difference > tolerance).then(|| InputChange::Retimed {
+                        from: *from,
+                        to: *to,
+                        input: write_input(input),
+                    })
+                }
+                Step::Removed(index) => {
+                    let (at, input) = &self.events[index];
+                    Some(InputChange::Removed {
+                        at: *at,
+                        input: write_input(input),
+                    })
+                }
+                Step::Added(index) => {
+                    let (at, input) = &new.events[index];
+                    Some(InputChange::Added {
+                        at: *at,
+                        input: write_input(input),
+                    })
+                }
+            })
+            .collect();
+
+        Ok(ScriptDiff { changes })
+    }
+}
+
+// How far apart positions and analog values can be and still match, re-recording never gets them exactly the same
+const PIXEL_TOLERANCE: f32 = 10.0;
+const ANALOG_TOLERANCE: f32 = 0.1;
+
+/// What the alignment compares an input by.
+#[derive(Clone, Copy)]
+struct Comparable {
+    // The same for inputs of the same kind on the same key, button or axis
+    id: u32,
+    // Scaled so that values within the tolerance are at most 1 apart
+    value: Vec2,
+}
+
+impl Comparable {
+    fn matches(&self, other: &Comparable) -> bool {
+        self.id == other.id && (self.value - other.value).abs().max_element() <= 1.0
+    }
+}
+
+// The input without its position or analog value, and that value
+fn split_value(input: &UserInput) -> (UserInput, Vec2) {
+    match input {
+        UserInput::MouseMove(_, Some(position)) => (
+            UserInput::MouseMove(Vec2::ZERO, Some(Vec2::ZERO)),
+            *position / PIXEL_TOLERANCE,
+        ),
+        UserInput::MouseMove(delta, None) => (
+            UserInput::MouseMove(Vec2::ZERO, None),
+            *delta / PIXEL_TOLERANCE,
+        ),
+        UserInput::Touch(phase, id, position, _) => (
+            UserInput::Touch(*phase, *id, Vec2::ZERO, None),
+            *position / PIXEL_TOLERANCE,
+        ),
+        UserInput::ControllerAxisChange(axis, value) => (
+            UserInput::ControllerAxisChange(*axis, 0.0),
+            Vec2::new(*value / ANALOG_TOLERANCE, 0.0),
+        ),
+        UserInput::ControllerButtonChange(button, value) => (
+            UserInput::ControllerButtonChange(*button, 0.0),
+            Vec2::new(*value / ANALOG_TOLERANCE, 0.0),
+        ),
+        input => (input.clone(), Vec2::ZERO),
+    }
+}
+
+// Longest common subsequence of the inputs, with Hirschberg's algorithm. Re-recorded mouse movement rarely matches
+// one to one, so most of two long recordings can differ, and a table of every pair of inputs wouldn't fit in memory.
+// The common start and end are skipped first, as that is all of it when a script was only edited a little.
+fn align(old: &[(Timestamp, UserInput)], new: &[(Timestamp, UserInput)]) -> Vec<Step> {
+    // Inputs are compared a lot, and their text form without the value is the same exactly when their kinds are
+    let mut ids = HashMap::new();
+    let mut comparable = |events: &[(Timestamp, UserInput)]| -> Vec<Comparable> {
+        events
+            .iter()
+            .map(|(_, input)| {
+                let (kind, value) = split_value(input);
+                let next_id = ids.len() as u32;
+                Comparable {
+                    id: *ids.entry(write_input(&kind)).or_insert(next_id),
+                    value,
+                }
+            })
+            .collect()
+    };
+    let old = comparable(old);
+    let new = comparable(new);
+
+    let prefix = old
+        .iter()
+        .zip(&new)
+        .take_while(|(old_input, new_input)| old_input.matches(new_input))
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old_input, new_input)| old_input.matches(new_input))
+        .count();
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+
+    let mut steps: Vec<Step> = (0..prefix).map(|index| Step::Same(index, index)).collect();
+    align_middle(
+        &old[prefix..old_end],
+        &new[prefix..new_end],
+        (prefix, prefix),
+        &mut steps,
+    );
+    steps.extend((0..suffix).map(|index| Step::Same(old_end + index, new_end + index)));
+    steps
+}
+
+// Splits the old inputs in half, and finds where to split the new ones so that the halves have the most in common
+fn align_middle(
+    old: &[Comparable],
+    new: &[Comparable],
+    (old_start, new_start): (usize, usize),
+    steps: &mut Vec<Step>,
+) {
+    match old {
+        [] => {
+            steps.extend((new_start..new_start + new.len()).map(Step::Added));
+        }
+        [input] => match new.iter().position(|new_input| new_input.matches(input)) {
+            Some(index) => {
+                steps.extend((new_start..new_start + index).map(Step::Added));
+                steps.push(Step::Same(old_start, new_start + index));
+                steps.extend((new_start + index + 1..new_start + new.len()).map(Step::Added));
+            }
+            None => {
+                steps.push(Step::Removed(old_start));
+                steps.extend((new_start..new_start + new.len()).map(Step::Added));
+            }
+        },
+        _ if new.is_empty() => {
+            steps.extend((old_start..old_start + old.len()).map(Step::Removed));
+        }
+        _ => {
+            let middle = old.len() / 2;
+            let forward = common_lengths(old[..middle].iter(), new.iter());
+            let backward = common_lengths(old[middle..].iter().rev(), new.iter().rev());
+
+            let split = (0..=new.len())
+                .max_by_key(|index| {
+                    (
+                        forward[*index] + backward[new.len() - index],
+                        usize::MAX - index,
+                    )
+                })
+                .unwrap();
+
+            align_middle(&old[..middle], &new[..split], (old_start, new_start), steps);
+            align_middle(
+                &old[middle..],
+                &new[split..],
+                (old_start + middle, new_start + split),
+                steps,
+            );
+        }
+    }
+}
+
+// Length of the longest common subsequence of `old` and each start of `new`, keeping only one row of the table
+fn common_lengths<'a>(
+    old: impl Iterator<Item = &'a Comparable>,
+    new: impl Iterator<Item = &'a Comparable> + Clone,
+) -> Vec<u32> {
+    let mut row = vec![0; new.clone().count() + 1];
+    for old_input in old {
+        let mut diagonal = 0;
+        for (index, new_input) in new.clone().enumerate() {
+            let above = row[index + 1];
+            row[index + 1] = if old_input.matches(new_input) {
+                diagonal + 1
+            } else {
+                above.max(row[index])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        input::gamepad::{GamepadAxisType, GamepadButtonType},
+        prelude::{Gamepad, GamepadAxis, GamepadButton},
+    };
+
+    use super::*;
+
+    fn script(events: &[(u32, &str)]) -> TestScript {
+        TestScript {
+            events: events
+                .iter()
+                .map(|(frame, char)| {
+                    let input = match *char {
+                        "quit" => UserInput::Quit,
+                        char => UserInput::Character(char.to_string()),
+                    };
+                    (Timestamp::Frame(*frame), input)
+                })
+                .collect(),
+            expect_failure: false,
+        }
+    }
+
+    #[test]
+    fn same_scripts_have_no_changes() {
+        let old = script(&[(0, "a"), (10, "b"), (20, "quit")]);
+        let diff = old.diff(&old.clone(), Timestamp::Frame(0)).unwrap();
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+    }
+
+    #[test]
+    fn finds_added_removed_and_retimed_inputs() {
+        let old = script(&[(0, "a"), (10, "b"), (20, "c"), (30, "d"), (40, "quit")]);
+        let new = script(&[(0, "a"), (25, "c"), (28, "e"), (31, "d"), (40, "quit")]);
+        let diff = old.diff(&new, Timestamp::Frame(2)).unwrap();
+
+        assert_eq!(
+            diff.changes,
+            [
+                InputChange::Removed {
+                    at: Timestamp::Frame(10),
+                    input: r#"char "b""#.to_string(),
+                },
+                InputChange::Retimed {
+                    from: Timestamp::Frame(20),
+                    to: Timestamp::Frame(25),
+                    input: r#"char "c""#.to_string(),
+                },
+                InputChange::Added {
+                    at: Timestamp::Frame(28),
+                    input: r#"char "e""#.to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            concat!(
+                "-          10f  char \"b\"\n",
+                "~          25f  char \"c\" (was at 20f)\n",
+                "+          28f  char \"e\"\n",
+            )
+        );
+    }
+
+    #[test]
+    fn retimed_inputs_have_to_move_past_the_tolerance() {
+        let second = |millis| Timestamp::Time(Duration::from_millis(millis));
+        let at = |millis, input| (second(millis), input);
+        let old = TestScript {
+            events: vec![at(1000, UserInput::ImeEnabled), at(2000, UserInput::Quit)],
+            expect_failure: false,
+        };
+        let new = TestScript {
+            events: vec![at(1100, UserInput::ImeEnabled), at(1850, UserInput::Quit)],
+            expect_failure: false,
+        };
+
+        let diff = old.diff(&new, second(100)).unwrap();
+        assert_eq!(
+            diff.changes,
+            [InputChange::Retimed {
+                from: second(2000),
+                to: second(1850),
+                input: "quit".to_string(),
+            }]
+        );
+        assert!(old.diff(&new, second(150)).unwrap().is_empty());
+        assert!(old.diff(&new, Timestamp::Frame(1)).is_err());
+    }
+
+    #[test]
+    fn aligns_scripts_that_have_nothing_in_common() {
+        let old = script(&[(0, "a"), (1, "b")]);
+        let new = script(&[(0, "c")]);
+        let diff = old.diff(&new, Timestamp::Frame(0)).unwrap();
+
+        let removed = |at: u32, input: &str| InputChange::Removed {
+            at: Timestamp::Frame(at),
+            input: format!("char \"{}\"", input),
+        };
+        assert_eq!(
+            diff.changes,
+            [
+                removed(0, "a"),
+                removed(1, "b"),
+                InputChange::Added {
+                    at: Timestamp::Frame(0),
+                    input: r#"char "c""#.to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            script(&[])
+                .diff(&old, Timestamp::Frame(0))
+                .unwrap()
+                .changes
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn analog_values_and_positions_match_within_a_tolerance() {
+        let pad = Gamepad::new(0);
+        let stick = GamepadAxis::new(pad, GamepadAxisType::LeftStickX);
+        let trigger = GamepadButton::new(pad, GamepadButtonType::RightTrigger2);
+        let frames = |inputs: Vec<UserInput>| TestScript {
+            events: inputs
+                .into_iter()
+                .enumerate()
+                .map(|(frame, input)| (Timestamp::Frame(frame as u32), input))
+                .collect(),
+            expect_failure: false,
+        };
+        let old = frames(vec![
+            UserInput::MouseMove(Vec2::new(1.0, 0.0), Some(Vec2::new(100.0, 100.0))),
+            UserInput::ControllerAxisChange(stick, 0.5),
+            UserInput::ControllerButtonChange(trigger, 1.0),
+            UserInput::MouseMove(Vec2::new(1.0, 0.0), Some(Vec2::new(200.0, 100.0))),
+        ]);
+        let new = frames(vec![
+            UserInput::MouseMove(Vec2::new(3.0, 2.0), Some(Vec2::new(104.0, 95.0))),
+            UserInput::ControllerAxisChange(stick, 0.55),
+            UserInput::ControllerButtonChange(trigger, 0.95),
+            UserInput::MouseMove(Vec2::new(1.0, 0.0), Some(Vec2::new(250.0, 100.0))),
+        ]);
+
+        let diff = old.diff(&new, Timestamp::Frame(0)).unwrap();
+        let lines: Vec<_> = diff.changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "-           3f  move 1 0 200 100",
+                "+           3f  move 1 0 250 100",
+            ]
+        );
+
+        let other_axis = frames(vec![UserInput::ControllerAxisChange(
+            GamepadAxis::new(pad, GamepadAxisType::LeftStickY),
+            0.5,
+        )]);
+        assert_eq!(
+            frames(vec![UserInput::ControllerAxisChange(stick, 0.5)])
+                .diff(&other_axis, Timestamp::Frame(0))
+                .unwrap()
+                .changes
+                .len(),
+            2
+        );
+    }
+
+    // Without skipping the common start and end, this would compare ten billion pairs of inputs
+    #[test]
+    fn long_scripts_with_small_edits_diff_quickly() {
+        let characters: Vec<_> = (0..100_000u32)
+            .map(|frame| (frame, ["a", "b", "c"][frame as usize % 3]))
+            .collect();
+        let old = script(&characters);
+        let mut new = old.clone();
+        new.events[50_000].1 = UserInput::Character("d".to_string());
+
+        let diff = old.diff(&new, Timestamp::Frame(0)).unwrap();
+        assert_eq!(diff.changes.len(), 2);
+    }
+
+    // The alignment should keep as many inputs as the whole table would, and go through both scripts in order
+    #[test]
+    fn alignment_keeps_the_longest_common_inputs() {
+        fn longest_common(old: &[u32], new: &[u32]) -> usize {
+            let mut table = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for (i, old_id) in old.iter().enumerate() {
+                for (j, new_id) in new.iter().enumerate() {
+                    table[i + 1][j + 1] = if old_id == new_id {
+                        table[i][j] + 1
+                    } else {
+                        table[i][j + 1].max(table[i + 1][j])
+                    };
+                }
+            }
+            table[old.len()][new.len()]
+        }
+
+        // A small linear congruential generator, so the cases are the same on every run
+        let mut seed = 12345u32;
+        let mut random = |below: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % below
+        };
+
+        for _ in 0..200 {
+            let old_ids: Vec<u32> = (0..random(12)).map(|_| random(3)).collect();
+            let new_ids: Vec<u32> = (0..random(12)).map(|_| random(3)).collect();
+            let events = |ids: &[u32]| -> Vec<(Timestamp, UserInput)> {
+                ids.iter()
+                    .enumerate()
+                    .map(|(frame, id)| {
+                        let input = UserInput::Character(id.to_string());
+                        (Timestamp::Frame(frame as u32), input)
+                    })
+                    .collect()
+            };
+            let old = events(&old_ids);
+            let new = events(&new_ids);
+
+            let steps = align(&old, &new);
+            let (mut next_old, mut next_new, mut same) = (0, 0, 0);
+            for step in steps {
+                match step {
+                    Step::Same(old_index, new_index) => {
+                        assert_eq!((old_index, new_index), (next_old, next_new));
+                        assert_eq!(old[old_index].1, new[new_index].1);
+                        next_old += 1;
+                        next_new += 1;
+                        same += 1;
+                    }
+                    Step::Removed(index) => {
+                        assert_eq!(index, next_old);
+                        next_old += 1;
+                    }
+                    Step::Added(index) => {
+                        assert_eq!(index, next_new);
+                        next_new += 1;
+                    }
+                }
+            }
+
+            assert_eq!((next_old, next_new), (old.len(), new.len()));
+            assert_eq!(same, longest_common(&old_ids, &new_ids));
+        }
+    }
+}
@@ -0,0 +1,657 @@
+use std::{io, path::Path, time::Duration};
+
+use bevy::prelude::{GamepadAxis, GamepadButton, KeyCode, MouseButton};
+
+use super::{
+    read_script,
+    text_format::{check_order, write_input},
+    write_script, ScriptFormat, TestScript, Timestamp, UserInput,
+};
+
+impl TestScript {
+    /// Reads a script in any format, and tells which one it was in.
+    pub fn read(path: &Path) -> Result<(Self, ScriptFormat), String> {
+        read_script(path)
+    }
+
+    /// Saves the script in the given format.
+    pub fn write(&self, path: &Path, format: ScriptFormat) -> io::Result<()> {
+        write_script(path, self, format)
+    }
+
+    /// The inputs, one per line.
+    pub fn timeline(&self) -> String {
+        self.events
+            .iter()
+            .map(|(timestamp, input)| format!("{:>12}  {}\n", timestamp, write_input(input)))
+            .collect()
+    }
+
+    /// How many inputs there are, not counting markers or the end of the script.
+    pub fn len(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|(_, input)| !matches!(input, UserInput::Marker(_) | UserInput::Quit))
+            .count()
+    }
+
+    /// True if the script has no inputs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True if the script was recorded to fail, and passes only if the test fails.
+    pub fn expects_failure(&self) -> bool {
+        self.expect_failure
+    }
+
+    /// When the script ends and playback starts asserting.
+    pub fn end(&self) -> Option<Timestamp> {
+        self.events.last().map(|(timestamp, _)| *timestamp)
+    }
+
+    /// Drops the inputs before `until`, and moves the rest so that the script starts from there.
+    /// Pads stay connected, and the buttons held at `until` are pressed right at the start.
+    pub fn trim_start(&mut self, until: Timestamp) -> Result<(), String> {
+        self.check_kind(until)?;
+        self.remove_span(until.zero(), until)
+    }
+
+    /// Drops the inputs after `from`, and ends the script there.
+    pub fn trim_end(&mut self, from: Timestamp) -> Result<(), String> {
+        self.check_kind(from)?;
+
+        self.events
+            .retain(|(timestamp, input)| *timestamp <= from || is_quit(input));
+        for (timestamp, _) in &mut self.events {
+            *timestamp = (*timestamp).min(from);
+        }
+
+        Ok(())
+    }
+
+    /// Drops the inputs from `from` up to `to`, and moves the ones after back to close the gap.
+    /// Pads stay connected, and buttons pressed or released during the cut are pressed or released at `from`.
+    pub fn cut(&mut self, from: Timestamp, to: Timestamp) -> Result<(), String> {
+        self.check_kind(from)?;
+        self.check_kind(to)?;
+        if to < from {
+            return Err(format!(
+                "the cut ends at {} before it starts at {}",
+                to, from
+            ));
+        }
+
+        self.remove_span(from, to)
+    }
+
+    /// Delays the inputs from `from` onwards by `by`.
+    pub fn shift_later(&mut self, from: Timestamp, by: Timestamp) -> Result<(), String> {
+        self.check_kind(from)?;
+        self.check_kind(by)?;
+
+        let shifted = self
+            .events
+            .iter()
+            .map(|(timestamp, _)| {
+                if *timestamp >= from {
+                    plus(*timestamp, by)
+                } else {
+                    Ok(*timestamp)
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for ((timestamp, _), shifted) in self.events.iter_mut().zip(shifted) {
+            *timestamp = shifted;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the inputs from `from` onwards earlier by `by`, but not past the inputs before them.
+    pub fn shift_earlier(&mut self, from: Timestamp, by: Timestamp) -> Result<(), String> {
+        self.check_kind(from)?;
+        self.check_kind(by)?;
+
+        let earliest = self
+            .events
+            .iter()
+            .map(|(timestamp, _)| *timestamp)
+            .filter(|timestamp| *timestamp < from)
+            .max();
+
+        for (timestamp, _) in &mut self.events {
+            if *timestamp >= from {
+                let shifted = minus(*timestamp, by);
+                *timestamp = earliest.map_or(shifted, |earliest| shifted.max(earliest));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Continues the script with the inputs of `other`, `gap` after this one ends.
+    pub fn append(&mut self, other: &TestScript, gap: Timestamp) -> Result<(), String> {
+        self.check_kind(gap)?;
+        other.check_kind(gap)?;
+
+        let offset = plus(self.end().unwrap_or(gap.zero()), gap)?;
+        let appended = other
+            .events
+            .iter()
+            .map(|(timestamp, input)| Ok((plus(*timestamp, offset)?, input.clone())))
+            .collect::<Result<Vec<_>, String>>()?;
+        self.events.retain(|(_, input)| !is_quit(input));
+        self.events.extend(appended);
+
+        Ok(())
+    }
+
+    /// Things that would make playback go wrong, empty if there are none.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        for (index, pair) in self.events.windows(2).enumerate() {
+            if let Err(problem) = check_order(&pair[0].0, &pair[1].0) {
+                problems.push(format!("input {}: {}", index + 2, problem));
+            }
+        }
+
+        let quits = self
+            .events
+            .iter()
+            .filter(|(_, input)| is_quit(input))
+            .count();
+        if quits > 1 {
+            problems.push(format!(
+                "ends {} times, playback starts asserting at the first one",
+                quits
+            ));
+        }
+
+        if !self.events.last().is_some_and(|(_, input)| is_quit(input)) {
+            problems.push("doesn't end, so playback would never start asserting".to_string());
+        }
+
+        problems
+    }
+
+    // Everything after the span moves back to `from`. The end and the pad connections in the span are kept at `from`,
+    // and so are the last presses, releases and analog values that left a button or axis different than before it.
+    fn remove_span(&mut self, from: Timestamp, to: Timestamp) -> Result<(), String> {
+        let (before, rest): (Vec<_>, Vec<_>) = self
+            .events
+            .drain(..)
+            .partition(|(timestamp, _)| *timestamp < from);
+        let (removed, after): (Vec<_>, Vec<_>) =
+            rest.into_iter().partition(|(timestamp, _)| *timestamp < to);
+
+        let mut changed: Vec<&UserInput> = vec![];
+        for (_, input) in &removed {
+            let Some((control, value)) = control_value(input) else {
+                continue;
+            };
+            changed
+                .retain(|earlier| control_value(earlier).map(|(other, _)| other) != Some(control));
+            let value_before = before
+                .iter()
+                .rev()
+                .find_map(|(_, earlier)| {
+                    control_value(earlier).filter(|(other, _)| *other == control)
+                })
+                .map_or(0.0, |(_, value)| value);
+            if value != value_before {
+                changed.push(input);
+            }
+        }
+
+        let connects = removed
+            .iter()
+            .filter(|(_, input)| matches!(input, UserInput::ControllerConnect(..)));
+        let quits = removed.iter().filter(|(_, input)| is_quit(input));
+        let restored: Vec<_> = connects
+            .map(|(_, input)| input)
+            .chain(changed)
+            .chain(quits.map(|(_, input)| input))
+            .map(|input| (from, input.clone()))
+            .collect();
+
+        let moved = after
+            .into_iter()
+            .map(|(timestamp, input)| Ok((plus(minus(timestamp, to), from)?, input)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        self.events = before;
+        self.events.extend(restored);
+        self.events.extend(moved);
+
+        Ok(())
+    }
+
+    pub(super) fn check_kind(&self, timestamp: Timestamp) -> Result<(), String> {
+        match self.events.first() {
+            Some((first, _)) if kind(first) != kind(&timestamp) => Err(format!(
+                "the script is in {}, but {} is in {}",
+                kind(first),
+                timestamp,
+                kind(&timestamp)
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn is_quit(input: &UserInput) -> bool {
+    matches!(input, UserInput::Quit)
+}
+
+/// A button or axis an input changes, keys are the same control whether pressed with a logical key or not.
+#[derive(Clone, Copy, PartialEq)]
+enum Control {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    PadButton(GamepadButton),
+    PadAxis(GamepadAxis),
+}
+
+// Pressed is 1 and released is 0, like the analog values
+fn control_value(input: &UserInput) -> Option<(Control, f32)> {
+    Some(match input {
+        UserInput::KeyPress(key) | UserInput::LogicalKeyPress(key, _) => (Control::Key(*key), 1.0),
+        UserInput::KeyRelese(key) | UserInput::LogicalKeyRelease(key, _) => {
+            (Control::Key(*key), 0.0)
+        }
+        UserInput::MouseButtonPress(button) => (Control::Mouse(*button), 1.0),
+        UserInput::MouseButtonRelease(button) => (Control::Mouse(*button), 0.0),
+        UserInput::ControllerButtonPress(button) => (Control::PadButton(*button), 1.0),
+        UserInput::ControllerButtonRelease(button) => (Control::PadButton(*button), 0.0),
+        UserInput::ControllerButtonChange(button, value) => (Control::PadButton(*button), *value),
+        UserInput::ControllerAxisChange(axis, value) => (Control::PadAxis(*axis), *value),
+        _ => return None,
+    })
+}
+
+fn kind(timestamp: &Timestamp) -> &'static str {
+    match timestamp {
+        Timestamp::Time(_) => "seconds",
+        Timestamp::Frame(_) => "frames",
+    }
+}
+
+// The kinds are checked before these are used
+fn plus(a: Timestamp, b: Timestamp) -> Result<Timestamp, String> {
+    match (a, b) {
+        (Timestamp::Time(a_time), Timestamp::Time(b_time)) => {
+            a_time.checked_add(b_time).map(Timestamp::Time)
+        }
+        (Timestamp::Frame(a_frame), Timestamp::Frame(b_frame)) => {
+            a_frame.checked_add(b_frame).map(Timestamp::Frame)
+        }
+        _ => unreachable!("mixed times and frames"),
+    }
+    .ok_or_else(|| format!("{} after {} is too late for a script", b, a))
+}
+
+pub(super) fn minus(a: Timestamp, b: Timestamp) -> Timestamp {
+    match (a, b) {
+        (Timestamp::Time(a), Timestamp::Time(b)) => Timestamp::Time(a.saturating_sub(b)),
+        (Timestamp::Frame(a), Timestamp::Frame(b)) => Timestamp::Frame(a.saturating_sub(b)),
+        _ => unreachable!("mixed times and frames"),
+    }
+}
+
+impl Timestamp {
+    /// The start of a script of the same kind.
+    pub fn zero(&self) -> Timestamp {
+        match self {
+            Timestamp::Time(_) => Timestamp::Time(Duration::ZERO),
+            Timestamp::Frame(_) => Timestamp::Frame(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{input::gamepad::GamepadButtonType, prelude::Gamepad};
+
+    use super::*;
+
+    // Characters named after their frame, so they can be followed through the edits
+    fn script(frames: &[u32], end: u32) -> TestScript {
+        let mut events: Vec<_> = frames
+            .iter()
+            .map(|frame| {
+                (
+                    Timestamp::Frame(*frame),
+                    UserInput::Character(frame.to_string()),
+                )
+            })
+            .collect();
+        events.push((Timestamp::Frame(end), UserInput::Quit));
+
+        TestScript {
+            events,
+            expect_failure: false,
+        }
+    }
+
+    fn frames(script: &TestScript) -> Vec<(u32, String)> {
+        script
+            .events
+            .iter()
+            .map(|(timestamp, input)| {
+                let Timestamp::Frame(frame) = timestamp else {
+                    panic!("expected frames, got {}", timestamp);
+                };
+                let name = match input {
+                    UserInput::Character(name) => name.clone(),
+                    _ => write_input(input),
+                };
+                (*frame, name)
+            })
+            .collect()
+    }
+
+    fn expected(events: &[(u32, &str)]) -> Vec<(u32, String)> {
+        events
+            .iter()
+            .map(|(frame, name)| (*frame, name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn cut_drops_from_the_start_up_to_the_end() {
+        let mut edited = script(&[5, 10, 15, 20, 25], 30);
+        edited
+            .cut(Timestamp::Frame(10), Timestamp::Frame(20))
+            .unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (10, "20"), (15, "25"), (20, "quit")])
+        );
+    }
+
+    #[test]
+    fn cut_at_the_boundaries_of_the_script() {
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .cut(Timestamp::Frame(0), Timestamp::Frame(5))
+            .unwrap();
+        assert_eq!(
+            frames(&edited),
+            expected(&[(0, "5"), (5, "10"), (10, "quit")])
+        );
+
+        // Nothing is cut, and nothing moves
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .cut(Timestamp::Frame(5), Timestamp::Frame(5))
+            .unwrap();
+        assert_eq!(frames(&edited), frames(&script(&[5, 10], 15)));
+
+        // The end of the script is kept, at the start of the cut
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .cut(Timestamp::Frame(10), Timestamp::Frame(40))
+            .unwrap();
+        assert_eq!(frames(&edited), expected(&[(5, "5"), (10, "quit")]));
+    }
+
+    #[test]
+    fn cut_keeps_every_nanosecond_before_its_end() {
+        let time = |secs, nanos| Timestamp::Time(Duration::new(secs, nanos));
+        let mut edited = TestScript {
+            events: vec![
+                (time(0, 999_999_999), UserInput::ImeEnabled),
+                (time(1, 0), UserInput::ImeDisabled),
+                (time(1, 999_999_999), UserInput::ImeEnabled),
+                (time(2, 0), UserInput::ImeDisabled),
+                (time(3, 0), UserInput::Quit),
+            ],
+            expect_failure: false,
+        };
+        edited.cut(time(1, 0), time(2, 0)).unwrap();
+
+        assert_eq!(
+            edited.events,
+            [
+                (time(0, 999_999_999), UserInput::ImeEnabled),
+                (time(1, 0), UserInput::ImeDisabled),
+                (time(2, 0), UserInput::Quit),
+            ]
+        );
+    }
+
+    #[test]
+    fn cut_rejects_an_end_before_the_start() {
+        let mut edited = script(&[5], 10);
+        assert!(edited
+            .cut(Timestamp::Frame(8), Timestamp::Frame(4))
+            .is_err());
+        assert_eq!(frames(&edited), frames(&script(&[5], 10)));
+    }
+
+    #[test]
+    fn shift_earlier_stops_at_the_inputs_before() {
+        let mut edited = script(&[5, 10, 20, 25], 30);
+        edited
+            .shift_earlier(Timestamp::Frame(20), Timestamp::Frame(15))
+            .unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (10, "10"), (10, "20"), (10, "25"), (15, "quit")])
+        );
+    }
+
+    #[test]
+    fn shift_earlier_at_the_boundaries_of_the_script() {
+        // An input right at `from` moves, and nothing goes before the start
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .shift_earlier(Timestamp::Frame(5), Timestamp::Frame(8))
+            .unwrap();
+        assert_eq!(
+            frames(&edited),
+            expected(&[(0, "5"), (2, "10"), (7, "quit")])
+        );
+
+        // Can move inputs right up to the ones before, but not past
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .shift_earlier(Timestamp::Frame(10), Timestamp::Frame(5))
+            .unwrap();
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (5, "10"), (10, "quit")])
+        );
+
+        // Past the end, nothing moves
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .shift_earlier(Timestamp::Frame(16), Timestamp::Frame(5))
+            .unwrap();
+        assert_eq!(frames(&edited), frames(&script(&[5, 10], 15)));
+    }
+
+    #[test]
+    fn shift_later_moves_from_the_boundary_on() {
+        let mut edited = script(&[5, 10], 15);
+        edited
+            .shift_later(Timestamp::Frame(10), Timestamp::Frame(3))
+            .unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (13, "10"), (18, "quit")])
+        );
+    }
+
+    #[test]
+    fn trims_both_ends() {
+        let mut edited = script(&[5, 10, 15], 20);
+        edited.trim_start(Timestamp::Frame(10)).unwrap();
+        assert_eq!(
+            frames(&edited),
+            expected(&[(0, "10"), (5, "15"), (10, "quit")])
+        );
+
+        edited.trim_end(Timestamp::Frame(5)).unwrap();
+        assert_eq!(
+            frames(&edited),
+            expected(&[(0, "10"), (5, "15"), (5, "quit")])
+        );
+    }
+
+    fn with_inputs(mut script: TestScript, inputs: Vec<(u32, UserInput)>) -> TestScript {
+        script.events.extend(
+            inputs
+                .into_iter()
+                .map(|(frame, input)| (Timestamp::Frame(frame), input)),
+        );
+        script.events.sort_by_key(|(timestamp, _)| *timestamp);
+        script
+    }
+
+    #[test]
+    fn trim_start_keeps_pads_and_held_buttons() {
+        let pad = Gamepad::new(1);
+        let trigger = GamepadButton::new(pad, GamepadButtonType::RightTrigger2);
+        let mut edited = with_inputs(
+            script(&[], 30),
+            vec![
+                (1, UserInput::ControllerConnect(pad, "Pad".to_string())),
+                (2, UserInput::KeyPress(KeyCode::KeyA)),
+                (3, UserInput::KeyPress(KeyCode::KeyB)),
+                (4, UserInput::KeyRelese(KeyCode::KeyB)),
+                (5, UserInput::ControllerButtonChange(trigger, 0.5)),
+                (15, UserInput::KeyRelese(KeyCode::KeyA)),
+            ],
+        );
+        edited.trim_start(Timestamp::Frame(10)).unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[
+                (0, "pad-connect 1 \"Pad\""),
+                (0, "press KeyA"),
+                (0, "pad-button 1 RightTrigger2 0.5"),
+                (5, "release KeyA"),
+                (20, "quit"),
+            ])
+        );
+    }
+
+    #[test]
+    fn cut_releases_what_was_let_go_during_it() {
+        let mut edited = with_inputs(
+            script(&[], 30),
+            vec![
+                (5, UserInput::MouseButtonPress(MouseButton::Left)),
+                (12, UserInput::MouseButtonRelease(MouseButton::Left)),
+                (13, UserInput::KeyPress(KeyCode::KeyA)),
+                (14, UserInput::KeyRelese(KeyCode::KeyA)),
+                (15, UserInput::KeyRelese(KeyCode::KeyB)),
+                (25, UserInput::MouseButtonPress(MouseButton::Left)),
+            ],
+        );
+        edited
+            .cut(Timestamp::Frame(10), Timestamp::Frame(20))
+            .unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[
+                (5, "mouse-press Left"),
+                (10, "mouse-release Left"),
+                (15, "mouse-press Left"),
+                (20, "quit"),
+            ])
+        );
+    }
+
+    #[test]
+    fn edits_reject_timestamps_past_the_end_of_time() {
+        let mut edited = script(&[5, 10], 15);
+        let error = edited
+            .shift_later(Timestamp::Frame(10), Timestamp::Frame(u32::MAX))
+            .unwrap_err();
+        assert!(error.contains("too late"), "{}", error);
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (10, "10"), (15, "quit")])
+        );
+
+        let error = edited
+            .append(&script(&[], 1), Timestamp::Frame(u32::MAX))
+            .unwrap_err();
+        assert!(error.contains("too late"), "{}", error);
+    }
+
+    #[test]
+    fn append_continues_after_the_end() {
+        let mut edited = script(&[5], 10);
+        edited
+            .append(&script(&[0, 4], 6), Timestamp::Frame(2))
+            .unwrap();
+
+        assert_eq!(
+            frames(&edited),
+            expected(&[(5, "5"), (12, "0"), (16, "4"), (18, "quit")])
+        );
+    }
+
+    #[test]
+    fn edits_reject_the_other_kind_of_timestamp() {
+        let mut edited = script(&[5], 10);
+        let time = Timestamp::Time(Duration::from_secs(1));
+
+        assert_eq!(
+            edited.cut(time, time).unwrap_err(),
+            "the script is in frames, but 1.000s is in seconds"
+        );
+        assert!(edited.shift_earlier(Timestamp::Frame(1), time).is_err());
+        assert!(edited.trim_start(time).is_err());
+        assert_eq!(frames(&edited), frames(&script(&[5], 10)));
+    }
+
+    #[test]
+    fn validate_finds_what_would_break_playback() {
+        assert!(script(&[5, 10], 15).validate().is_empty());
+
+        let mut broken = script(&[10, 5], 15);
+        broken.events.push((Timestamp::Frame(20), UserInput::Quit));
+        assert_eq!(
+            broken.validate(),
+            [
+                "input 2: frame is earlier than the one before it",
+                "ends 2 times, playback starts asserting at the first one",
+            ]
+        );
+
+        broken.events.pop();
+        broken.events.pop();
+        assert_eq!(
+            broken.validate().last().unwrap(),
+            "doesn't end, so playback would never start asserting"
+        );
+    }
+
+    #[test]
+    fn length_only_counts_inputs() {
+        let counted = script(&[5, 10], 15);
+        assert_eq!(counted.len(), 2);
+        assert!(!counted.is_empty());
+        assert_eq!(counted.end(), Some(Timestamp::Frame(15)));
+
+        assert!(script(&[], 15).is_empty());
+
+        let mut marked = script(&[], 15);
+        marked.events.insert(
+            0,
+            (Timestamp::Frame(5), UserInput::Marker("boss".to_string())),
+        );
+        assert!(marked.is_empty());
+    }
+}
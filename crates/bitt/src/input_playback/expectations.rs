@@ -0,0 +1,160 @@
+use std::{ops::Range, time::Duration};
+
+use bevy::{core::FrameCount, prelude::*};
+
+use crate::PlaybackTestingOptions;
+
+use super::{
+    playback::{fail_early, StartAsserting},
+    StartTime,
+};
+
+/// Adds checks that have to pass within a window of the replayed script.
+pub trait ExpectationAppExt {
+    /// Adds a check like `add_expectation("first point", 3.0..5.0, |points: Res<Points>| points.0 >= 1)`, which
+    /// has to return true at least once between 3 and 5 seconds into the script.
+    /// The window follows the script, so it is stretched along with it by `PlaybackTestingOptions::playback_rate`.
+    /// The check only runs while its window is open, in `PostUpdate`. If it hasn't returned true by the time the
+    /// window closes, the test fails right away. The test also fails if it passes before then.
+    /// Expectations aren't checked while recording.
+    fn add_expectation<M>(
+        &mut self,
+        name: impl Into<String>,
+        window: Range<f32>,
+        check: impl IntoSystem<(), bool, M>,
+    ) -> &mut Self;
+}
+
+impl ExpectationAppExt for App {
+    fn add_expectation<M>(
+        &mut self,
+        name: impl Into<String>,
+        window: Range<f32>,
+        check: impl IntoSystem<(), bool, M>,
+    ) -> &mut Self {
+        assert!(
+            window.start < window.end,
+            "the window of an expectation has to end after it starts"
+        );
+
+        let mut expectations = self
+            .init_resource::<ExpectationCheck>()
+            .world
+            .resource_mut::<ExpectationCheck>();
+        let index = expectations.expectations.len();
+        expectations.expectations.push(Expectation {
+            name: name.into(),
+            window: Duration::from_secs_f32(window.start)..Duration::from_secs_f32(window.end),
+            met: false,
+        });
+
+        self.add_systems(
+            PostUpdate,
+            check
+                .pipe(
+                    move |In(holds): In<bool>, mut expectations: ResMut<ExpectationCheck>| {
+                        if holds {
+                            expectations.expectations[index].met = true;
+                        }
+                    },
+                )
+                .in_set(CheckExpectations)
+                .run_if(move |expectations: Res<ExpectationCheck>| expectations.is_open(index)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub(super) struct CheckExpectations;
+
+#[derive(Debug)]
+struct Expectation {
+    name: String,
+    window: Range<Duration>,
+    met: bool,
+}
+
+/// Tracks the expectations against the position in the script, and the first one that wasn't met.
+#[derive(Debug, Default, Resource)]
+pub(super) struct ExpectationCheck {
+    expectations: Vec<Expectation>,
+    // Only set during playback, so nothing is checked while recording
+    position: Option<Duration>,
+    problem: Option<String>,
+}
+
+impl ExpectationCheck {
+    pub(super) fn problem(&self) -> Option<&str> {
+        self.problem.as_deref()
+    }
+
+    /// Called when the test passes, the check fails if an expectation hasn't been met yet.
+    pub(super) fn finish(&mut self) {
+        if self.problem.is_some() {
+            return;
+        }
+
+        if let Some(expectation) = self
+            .expectations
+            .iter()
+            .find(|expectation| !expectation.met)
+        {
+            self.problem = Some(format!(
+                "`{}` wasn't met, the test ended before its window closed at {:.3}s",
+                expectation.name,
+                expectation.window.end.as_secs_f32()
+            ));
+        }
+    }
+
+    fn is_open(&self, index: usize) -> bool {
+        let expectation = &self.expectations[index];
+        self.problem.is_none()
+            && !expectation.met
+            && self
+                .position
+                .is_some_and(|position| expectation.window.contains(&position))
+    }
+}
+
+pub(super) fn update_position(
+    mut expectations: ResMut<ExpectationCheck>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    options: Res<PlaybackTestingOptions>,
+    start_time: Option<Res<StartTime>>,
+) {
+    if let Some(start_time) = start_time {
+        let (elapsed, _) = start_time.script_position(&time, &frame_count, options.playback_rate);
+        expectations.position = Some(elapsed);
+    }
+}
+
+pub(super) fn close_windows(
+    mut expectations: ResMut<ExpectationCheck>,
+    mut start_events: EventWriter<StartAsserting>,
+) {
+    let Some(position) = expectations.position else {
+        return;
+    };
+    if expectations.problem.is_some() {
+        return;
+    }
+
+    let Some(expectation) = expectations
+        .expectations
+        .iter()
+        .find(|expectation| !expectation.met && expectation.window.end <= position)
+    else {
+        return;
+    };
+
+    let problem = format!(
+        "`{}` wasn't met between {:.3}s and {:.3}s",
+        expectation.name,
+        expectation.window.start.as_secs_f32(),
+        expectation.window.end.as_secs_f32()
+    );
+    fail_early("Expectation failed", &problem, &mut start_events);
+    expectations.problem = Some(problem);
+}
@@ -0,0 +1,131 @@
+use bevy::{
+    core::FrameCount,
+    input::{
+        gamepad::gamepad_event_system, keyboard::keyboard_input_system,
+        mouse::mouse_button_input_system, touch::touch_screen_input_system, InputSystem,
+    },
+    prelude::*,
+};
+
+use serde::Serialize;
+
+use crate::TestWrangler;
+
+use super::{
+    injection::{InputInjector, ScriptedButtonValues, ScriptedCursor},
+    StartTime, TestScript, Timestamp, UserInput,
+};
+
+/// Where to stop replaying an existing script and start recording, see `PlaybackTestingOptions::extend`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ExtendFrom {
+    /// Replay the whole script, and continue from where it ended.
+    End,
+    /// Replay the script up to the first marker with this name, and drop the rest of it.
+    Marker(String),
+}
+
+/// The part of the script to replay before recording continues, and when recording takes over.
+pub(super) fn replayed_part(
+    script: &TestScript,
+    from: &ExtendFrom,
+) -> Result<(TestScript, Timestamp), String> {
+    // Recording continues from the quit or the marker, and the marker itself is kept
+    let (handover, kept) = match from {
+        ExtendFrom::End => script
+            .events
+            .iter()
+            .position(|(_, input)| matches!(input, UserInput::Quit))
+            .map(|index| (index, index))
+            .ok_or_else(|| "the script doesn't end".to_string())?,
+        ExtendFrom::Marker(name) => script
+            .events
+            .iter()
+            .position(|(_, input)| matches!(input, UserInput::Marker(marker) if marker == name))
+            .map(|index| (index, index + 1))
+            .ok_or_else(|| format!("the script has no marker named `{}`", name))?,
+    };
+
+    let replayed = TestScript {
+        events: script.events[..kept].to_vec(),
+        expect_failure: script.expect_failure,
+    };
+
+    Ok((replayed, script.events[handover].0))
+}
+
+/// Replays the start of a script while the recording plugin waits, and then hands over to it.
+pub(crate) struct ExtendPlugin {
+    pub(crate) script: TestScript,
+    pub(crate) handover: Timestamp,
+}
+
+impl Plugin for ExtendPlugin {
+    fn build(&self, app: &mut App) {
+        // Real input isn't masked, as the pads connected by then have to stay connected for recording
+        app.insert_resource(Replay {
+            script: self.script.clone(),
+            handover: self.handover,
+            next_event: 0,
+            done: false,
+        })
+        .init_resource::<ScriptedCursor>()
+        .init_resource::<ScriptedButtonValues>()
+        .add_systems(
+            PreUpdate,
+            replay
+                .in_set(InputSystem)
+                .before(keyboard_input_system)
+                .before(mouse_button_input_system)
+                .before(gamepad_event_system)
+                .before(touch_screen_input_system),
+        );
+    }
+}
+
+/// How far the replay has gotten. Nothing is recorded until it is done.
+#[derive(Debug, Resource)]
+pub(super) struct Replay {
+    script: TestScript,
+    handover: Timestamp,
+    next_event: usize,
+    pub(super) done: bool,
+}
+
+fn replay(
+    mut replay: ResMut<Replay>,
+    mut wrangler: ResMut<TestWrangler>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    first_update: Option<Res<StartTime>>,
+    mut injector: InputInjector,
+) {
+    let Some(start_time) = first_update else {
+        return;
+    };
+    if replay.done {
+        return;
+    }
+
+    // Recording uses the real time, so the replay can't be sped up
+    let (elapsed, frames) = start_time.script_position(&time, &frame_count, 1.0);
+
+    let mut injected = false;
+    while let Some((timestamp, input)) = replay.script.events.get(replay.next_event) {
+        if !timestamp.reached(elapsed, frames) {
+            return;
+        }
+
+        injector.inject(input);
+        replay.next_event += 1;
+        injected = true;
+    }
+
+    // Inputs are recorded on the frame they are sent on, so the last replayed ones would be recorded again
+    if !injected && replay.handover.reached(elapsed, frames) {
+        // The game likely passed during the replay, but the test should pass at the end of the new part
+        wrangler.reset_outcome();
+        replay.done = true;
+        println!("Replay done, recording from {}", replay.handover);
+    }
+}
@@ -0,0 +1,796 @@
+use std::str::FromStr;
+
+use bevy::{
+    input::{
+        gamepad::{GamepadAxisType, GamepadButtonType},
+        keyboard::Key,
+        mouse::MouseScrollUnit,
+        touch::TouchPhase,
+    },
+    prelude::*,
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A name for a bevy enum variant that bitt keeps the same across bevy releases, so the scripts don't
+/// depend on how bevy happens to serialize its types. Variants without one, mostly ones with platform
+/// specific data, are saved with bevy's own encoding instead.
+pub(super) trait StableId: Sized + Serialize + DeserializeOwned {
+    fn id(&self) -> Option<String>;
+    fn from_id(id: &str) -> Option<Self>;
+}
+
+trait NamedVariants: Sized {
+    fn name(&self) -> Option<&'static str>;
+    fn from_name(name: &str) -> Option<Self>;
+}
+
+// The ids match the variant names of bevy 0.13. If bevy renames a variant, the id must stay the same.
+macro_rules! named_variants {
+    ($type:ty { $($variant:ident),* $(,)? }) => {
+        impl NamedVariants for $type {
+            fn name(&self) -> Option<&'static str> {
+                match self {
+                    $(Self::$variant => Some(stringify!($variant)),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(stringify!($variant) => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+named_variants!(KeyCode {
+    Backquote,
+    Backslash,
+    BracketLeft,
+    BracketRight,
+    Comma,
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Equal,
+    IntlBackslash,
+    IntlRo,
+    IntlYen,
+    KeyA,
+    KeyB,
+    KeyC,
+    KeyD,
+    KeyE,
+    KeyF,
+    KeyG,
+    KeyH,
+    KeyI,
+    KeyJ,
+    KeyK,
+    KeyL,
+    KeyM,
+    KeyN,
+    KeyO,
+    KeyP,
+    KeyQ,
+    KeyR,
+    KeyS,
+    KeyT,
+    KeyU,
+    KeyV,
+    KeyW,
+    KeyX,
+    KeyY,
+    KeyZ,
+    Minus,
+    Period,
+    Quote,
+    Semicolon,
+    Slash,
+    AltLeft,
+    AltRight,
+    Backspace,
+    CapsLock,
+    ContextMenu,
+    ControlLeft,
+    ControlRight,
+    Enter,
+    SuperLeft,
+    SuperRight,
+    ShiftLeft,
+    ShiftRight,
+    Space,
+    Tab,
+    Convert,
+    KanaMode,
+    Lang1,
+    Lang2,
+    Lang3,
+    Lang4,
+    Lang5,
+    NonConvert,
+    Delete,
+    End,
+    Help,
+    Home,
+    Insert,
+    PageDown,
+    PageUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    ArrowUp,
+    NumLock,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    NumpadAdd,
+    NumpadBackspace,
+    NumpadClear,
+    NumpadClearEntry,
+    NumpadComma,
+    NumpadDecimal,
+    NumpadDivide,
+    NumpadEnter,
+    NumpadEqual,
+    NumpadHash,
+    NumpadMemoryAdd,
+    NumpadMemoryClear,
+    NumpadMemoryRecall,
+    NumpadMemoryStore,
+    NumpadMemorySubtract,
+    NumpadMultiply,
+    NumpadParenLeft,
+    NumpadParenRight,
+    NumpadStar,
+    NumpadSubtract,
+    Escape,
+    Fn,
+    FnLock,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+    BrowserBack,
+    BrowserFavorites,
+    BrowserForward,
+    BrowserHome,
+    BrowserRefresh,
+    BrowserSearch,
+    BrowserStop,
+    Eject,
+    LaunchApp1,
+    LaunchApp2,
+    LaunchMail,
+    MediaPlayPause,
+    MediaSelect,
+    MediaStop,
+    MediaTrackNext,
+    MediaTrackPrevious,
+    Power,
+    Sleep,
+    AudioVolumeDown,
+    AudioVolumeMute,
+    AudioVolumeUp,
+    WakeUp,
+    Meta,
+    Hyper,
+    Turbo,
+    Abort,
+    Resume,
+    Suspend,
+    Again,
+    Copy,
+    Cut,
+    Find,
+    Open,
+    Paste,
+    Props,
+    Select,
+    Undo,
+    Hiragana,
+    Katakana,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+    F32,
+    F33,
+    F34,
+    F35,
+});
+
+named_variants!(Key {
+    Alt,
+    AltGraph,
+    CapsLock,
+    Control,
+    Fn,
+    FnLock,
+    NumLock,
+    ScrollLock,
+    Shift,
+    Symbol,
+    SymbolLock,
+    Meta,
+    Hyper,
+    Super,
+    Enter,
+    Tab,
+    Space,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    ArrowUp,
+    End,
+    Home,
+    PageDown,
+    PageUp,
+    Backspace,
+    Clear,
+    Copy,
+    CrSel,
+    Cut,
+    Delete,
+    EraseEof,
+    ExSel,
+    Insert,
+    Paste,
+    Redo,
+    Undo,
+    Accept,
+    Again,
+    Attn,
+    Cancel,
+    ContextMenu,
+    Escape,
+    Execute,
+    Find,
+    Help,
+    Pause,
+    Play,
+    Props,
+    Select,
+    ZoomIn,
+    ZoomOut,
+    BrightnessDown,
+    BrightnessUp,
+    Eject,
+    LogOff,
+    Power,
+    PowerOff,
+    PrintScreen,
+    Hibernate,
+    Standby,
+    WakeUp,
+    AllCandidates,
+    Alphanumeric,
+    CodeInput,
+    Compose,
+    Convert,
+    FinalMode,
+    GroupFirst,
+    GroupLast,
+    GroupNext,
+    GroupPrevious,
+    ModeChange,
+    NextCandidate,
+    NonConvert,
+    PreviousCandidate,
+    Process,
+    SingleCandidate,
+    HangulMode,
+    HanjaMode,
+    JunjaMode,
+    Eisu,
+    Hankaku,
+    Hiragana,
+    HiraganaKatakana,
+    KanaMode,
+    KanjiMode,
+    Katakana,
+    Romaji,
+    Zenkaku,
+    ZenkakuHankaku,
+    Soft1,
+    Soft2,
+    Soft3,
+    Soft4,
+    ChannelDown,
+    ChannelUp,
+    Close,
+    MailForward,
+    MailReply,
+    MailSend,
+    MediaClose,
+    MediaFastForward,
+    MediaPause,
+    MediaPlay,
+    MediaPlayPause,
+    MediaRecord,
+    MediaRewind,
+    MediaStop,
+    MediaTrackNext,
+    MediaTrackPrevious,
+    New,
+    Open,
+    Print,
+    Save,
+    SpellCheck,
+    Key11,
+    Key12,
+    AudioBalanceLeft,
+    AudioBalanceRight,
+    AudioBassBoostDown,
+    AudioBassBoostToggle,
+    AudioBassBoostUp,
+    AudioFaderFront,
+    AudioFaderRear,
+    AudioSurroundModeNext,
+    AudioTrebleDown,
+    AudioTrebleUp,
+    AudioVolumeDown,
+    AudioVolumeUp,
+    AudioVolumeMute,
+    MicrophoneToggle,
+    MicrophoneVolumeDown,
+    MicrophoneVolumeUp,
+    MicrophoneVolumeMute,
+    SpeechCorrectionList,
+    SpeechInputToggle,
+    LaunchApplication1,
+    LaunchApplication2,
+    LaunchCalendar,
+    LaunchContacts,
+    LaunchMail,
+    LaunchMediaPlayer,
+    LaunchMusicPlayer,
+    LaunchPhone,
+    LaunchScreenSaver,
+    LaunchSpreadsheet,
+    LaunchWebBrowser,
+    LaunchWebCam,
+    LaunchWordProcessor,
+    BrowserBack,
+    BrowserFavorites,
+    BrowserForward,
+    BrowserHome,
+    BrowserRefresh,
+    BrowserSearch,
+    BrowserStop,
+    AppSwitch,
+    Call,
+    Camera,
+    CameraFocus,
+    EndCall,
+    GoBack,
+    GoHome,
+    HeadsetHook,
+    LastNumberRedial,
+    Notification,
+    MannerMode,
+    VoiceDial,
+    TV,
+    TV3DMode,
+    TVAntennaCable,
+    TVAudioDescription,
+    TVAudioDescriptionMixDown,
+    TVAudioDescriptionMixUp,
+    TVContentsMenu,
+    TVDataService,
+    TVInput,
+    TVInputComponent1,
+    TVInputComponent2,
+    TVInputComposite1,
+    TVInputComposite2,
+    TVInputHDMI1,
+    TVInputHDMI2,
+    TVInputHDMI3,
+    TVInputHDMI4,
+    TVInputVGA1,
+    TVMediaContext,
+    TVNetwork,
+    TVNumberEntry,
+    TVPower,
+    TVRadioService,
+    TVSatellite,
+    TVSatelliteBS,
+    TVSatelliteCS,
+    TVSatelliteToggle,
+    TVTerrestrialAnalog,
+    TVTerrestrialDigital,
+    TVTimer,
+    AVRInput,
+    AVRPower,
+    ColorF0Red,
+    ColorF1Green,
+    ColorF2Yellow,
+    ColorF3Blue,
+    ColorF4Grey,
+    ColorF5Brown,
+    ClosedCaptionToggle,
+    Dimmer,
+    DisplaySwap,
+    DVR,
+    Exit,
+    FavoriteClear0,
+    FavoriteClear1,
+    FavoriteClear2,
+    FavoriteClear3,
+    FavoriteRecall0,
+    FavoriteRecall1,
+    FavoriteRecall2,
+    FavoriteRecall3,
+    FavoriteStore0,
+    FavoriteStore1,
+    FavoriteStore2,
+    FavoriteStore3,
+    Guide,
+    GuideNextDay,
+    GuidePreviousDay,
+    Info,
+    InstantReplay,
+    Link,
+    ListProgram,
+    LiveContent,
+    Lock,
+    MediaApps,
+    MediaAudioTrack,
+    MediaLast,
+    MediaSkipBackward,
+    MediaSkipForward,
+    MediaStepBackward,
+    MediaStepForward,
+    MediaTopMenu,
+    NavigateIn,
+    NavigateNext,
+    NavigateOut,
+    NavigatePrevious,
+    NextFavoriteChannel,
+    NextUserProfile,
+    OnDemand,
+    Pairing,
+    PinPDown,
+    PinPMove,
+    PinPToggle,
+    PinPUp,
+    PlaySpeedDown,
+    PlaySpeedReset,
+    PlaySpeedUp,
+    RandomToggle,
+    RcLowBattery,
+    RecordSpeedNext,
+    RfBypass,
+    ScanChannelsToggle,
+    ScreenModeNext,
+    Settings,
+    SplitScreenToggle,
+    STBInput,
+    STBPower,
+    Subtitle,
+    Teletext,
+    VideoModeNext,
+    Wink,
+    ZoomToggle,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+    F32,
+    F33,
+    F34,
+    F35,
+});
+
+named_variants!(MouseButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward
+});
+
+named_variants!(MouseScrollUnit { Line, Pixel });
+
+named_variants!(TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Canceled
+});
+
+named_variants!(GamepadButtonType {
+    South,
+    East,
+    North,
+    West,
+    C,
+    Z,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+});
+
+named_variants!(GamepadAxisType {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+});
+
+macro_rules! stable_id_from_name {
+    ($($type:ty),*) => {
+        $(impl StableId for $type {
+            fn id(&self) -> Option<String> {
+                self.name().map(str::to_string)
+            }
+
+            fn from_id(id: &str) -> Option<Self> {
+                Self::from_name(id)
+            }
+        })*
+    };
+}
+
+// Key codes with native scan codes and logical keys with characters fall back to bevy's encoding
+stable_id_from_name!(KeyCode, Key, MouseScrollUnit, TouchPhase);
+
+// The rest have an `Other` variant for buttons and axes without a name, those go as `Other(3)`
+macro_rules! stable_id_with_other {
+    ($($type:ty),*) => {
+        $(impl StableId for $type {
+            fn id(&self) -> Option<String> {
+                match self {
+                    Self::Other(index) => Some(format!("Other({})", index)),
+                    named => named.name().map(str::to_string),
+                }
+            }
+
+            fn from_id(id: &str) -> Option<Self> {
+                Self::from_name(id).or_else(|| parse_other(id).map(Self::Other))
+            }
+        })*
+    };
+}
+
+stable_id_with_other!(MouseButton, GamepadButtonType, GamepadAxisType);
+
+fn parse_other<T: FromStr>(id: &str) -> Option<T> {
+    id.strip_prefix("Other(")?.strip_suffix(')')?.parse().ok()
+}
+
+/// For `#[serde(with)]` on fields that should be saved by id.
+pub(super) mod stable_id {
+    use serde::de::Error;
+
+    use super::*;
+
+    pub(in super::super) fn serialize<T: StableId, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value.id() {
+            Some(id) => serializer.serialize_str(&id),
+            None => value.serialize(serializer),
+        }
+    }
+
+    pub(in super::super) fn deserialize<'de, T: StableId, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Encoded<T> {
+            Id(String),
+            Bevy(T),
+        }
+
+        match Encoded::<T>::deserialize(deserializer)? {
+            Encoded::Id(id) => {
+                T::from_id(&id).ok_or_else(|| D::Error::custom(format!("unknown id `{}`", id)))
+            }
+            Encoded::Bevy(value) => Ok(value),
+        }
+    }
+}
+
+/// For `#[serde(with)]` on `Gamepad` fields, so a pad is saved as its plain id.
+pub(super) mod gamepad {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        gamepad: &Gamepad,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(gamepad.id as u32)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Gamepad, D::Error> {
+        Ok(Gamepad::new(u32::deserialize(deserializer)? as usize))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedGamepadButton {
+    #[serde(with = "gamepad")]
+    gamepad: Gamepad,
+    #[serde(with = "stable_id")]
+    button_type: GamepadButtonType,
+}
+
+/// For `#[serde(with)]` on `GamepadButton` fields, so the button type is saved by id.
+pub(super) mod gamepad_button {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        button: &GamepadButton,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        EncodedGamepadButton {
+            gamepad: button.gamepad,
+            button_type: button.button_type,
+        }
+        .serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<GamepadButton, D::Error> {
+        let encoded = EncodedGamepadButton::deserialize(deserializer)?;
+        Ok(GamepadButton::new(encoded.gamepad, encoded.button_type))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedGamepadAxis {
+    #[serde(with = "gamepad")]
+    gamepad: Gamepad,
+    #[serde(with = "stable_id")]
+    axis_type: GamepadAxisType,
+}
+
+/// For `#[serde(with)]` on `GamepadAxis` fields, so the axis type is saved by id.
+pub(super) mod gamepad_axis {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        axis: &GamepadAxis,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        EncodedGamepadAxis {
+            gamepad: axis.gamepad,
+            axis_type: axis.axis_type,
+        }
+        .serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<GamepadAxis, D::Error> {
+        let encoded = EncodedGamepadAxis::deserialize(deserializer)?;
+        Ok(GamepadAxis::new(encoded.gamepad, encoded.axis_type))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedMouseWheel {
+    #[serde(with = "stable_id")]
+    unit: MouseScrollUnit,
+    x: f32,
+    y: f32,
+}
+
+/// For `#[serde(with)]` on `MouseWheel` fields. The window is left out, playback sends scrolls to the primary window.
+pub(super) mod mouse_wheel {
+    use bevy::input::mouse::MouseWheel;
+
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        wheel: &MouseWheel,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        EncodedMouseWheel {
+            unit: wheel.unit,
+            x: wheel.x,
+            y: wheel.y,
+        }
+        .serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<MouseWheel, D::Error> {
+        let encoded = EncodedMouseWheel::deserialize(deserializer)?;
+        Ok(MouseWheel {
+            unit: encoded.unit,
+            x: encoded.x,
+            y: encoded.y,
+            window: Entity::PLACEHOLDER,
+        })
+    }
+}
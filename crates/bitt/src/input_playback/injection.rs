@@ -0,0 +1,320 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::{
+        gamepad::{
+            GamepadAxisChangedEvent, GamepadButtonChangedEvent, GamepadConnection,
+            GamepadConnectionEvent, GamepadEvent, GamepadInfo,
+        },
+        keyboard::{Key, KeyboardInput, NativeKey},
+        mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+        touchpad::{TouchpadMagnify, TouchpadRotate},
+        ButtonState,
+    },
+    prelude::*,
+    window::PrimaryWindow,
+};
+
+use super::UserInput;
+
+/// Where the script last put the cursor, used to undo real mouse movement when isolating input.
+#[derive(Debug, Default, Resource)]
+pub(super) struct ScriptedCursor(Option<Vec2>);
+
+/// Analog button values as of the end of the last input update, used to undo the ones gilrs writes
+/// directly into `Axis<GamepadButton>` when isolating input.
+#[derive(Debug, Default, Resource)]
+pub(super) struct ScriptedButtonValues(Vec<(GamepadButton, f32)>);
+
+pub(super) fn remember_button_values(
+    button_axis: Res<Axis<GamepadButton>>,
+    mut scripted: ResMut<ScriptedButtonValues>,
+) {
+    scripted.0 = button_axis
+        .devices()
+        .filter_map(|button| button_axis.get(*button).map(|value| (*button, value)))
+        .collect();
+}
+
+// Runs `$handle::<E>(world)` for every event type a device can send.
+macro_rules! device_events {
+    ($handle:ident, $world:expr) => {
+        $handle::<KeyboardInput>($world);
+        $handle::<ReceivedCharacter>($world);
+        $handle::<Ime>($world);
+        $handle::<MouseButtonInput>($world);
+        $handle::<MouseMotion>($world);
+        $handle::<MouseWheel>($world);
+        $handle::<CursorMoved>($world);
+        $handle::<CursorEntered>($world);
+        $handle::<CursorLeft>($world);
+        $handle::<TouchInput>($world);
+        $handle::<TouchpadMagnify>($world);
+        $handle::<TouchpadRotate>($world);
+        $handle::<GamepadEvent>($world);
+        // Gilrs sends these directly for the pads that were plugged in at launch, the scripted ones go through `GamepadEvent`
+        $handle::<GamepadConnectionEvent>($world);
+    };
+}
+
+/// How many events of type `E` had been sent when the last input update finished.
+/// Everything sent after that came from a device.
+#[derive(Resource)]
+struct ScriptedEventCount<E: Event> {
+    count: usize,
+    marker: PhantomData<E>,
+}
+
+pub(super) fn remember_event_counts(world: &mut World) {
+    device_events!(remember_event_count, world);
+}
+
+fn remember_event_count<E: Event>(world: &mut World) {
+    let Some(events) = world.get_resource::<Events<E>>() else {
+        return;
+    };
+    let count = events_sent(events);
+    world.insert_resource(ScriptedEventCount::<E> {
+        count,
+        marker: PhantomData,
+    });
+}
+
+// Drops everything the devices sent since the last frame, so only the script can produce input.
+pub(super) fn mask_hardware_input(world: &mut World) {
+    device_events!(drop_device_events, world);
+
+    world.resource_scope(|world, scripted: Mut<ScriptedButtonValues>| {
+        let mut button_axis = world.resource_mut::<Axis<GamepadButton>>();
+        let buttons: Vec<_> = button_axis.devices().copied().collect();
+        for button in buttons {
+            button_axis.remove(button);
+        }
+        for (button, value) in &scripted.0 {
+            button_axis.set(*button, *value);
+        }
+    });
+
+    // Winit writes the cursor position straight into the window, so put back the scripted one
+    let cursor = world.resource::<ScriptedCursor>().0;
+    let mut window_query = world.query_filtered::<&mut Window, With<PrimaryWindow>>();
+    if let Ok(mut window) = window_query.get_single_mut(world) {
+        if window.cursor_position() != cursor {
+            window.set_cursor_position(cursor);
+        }
+    }
+}
+
+// Scripted events stay around until the next fixed update like any other, so they are only dropped together with
+// the device events if a device actually sent something since the last frame. Bevy can't remove just the newest events.
+fn drop_device_events<E: Event>(world: &mut World) {
+    let scripted = world
+        .get_resource::<ScriptedEventCount<E>>()
+        .map_or(0, |scripted| scripted.count);
+    if let Some(mut events) = world.get_resource_mut::<Events<E>>() {
+        if events_sent(&events) > scripted {
+            events.clear();
+        }
+    }
+}
+
+fn events_sent<E: Event>(events: &Events<E>) -> usize {
+    events.oldest_event_count() + events.len()
+}
+
+/// Sends scripted inputs as the same events winit and gilrs would send for real ones,
+/// so every consumer sees them the same way.
+#[derive(SystemParam)]
+pub(super) struct InputInjector<'w, 's> {
+    window_query: Query<'w, 's, (Entity, &'static mut Window), With<PrimaryWindow>>,
+    scripted_cursor: ResMut<'w, ScriptedCursor>,
+    keyboard: EventWriter<'w, KeyboardInput>,
+    characters: EventWriter<'w, ReceivedCharacter>,
+    ime: EventWriter<'w, Ime>,
+    mouse_buttons: EventWriter<'w, MouseButtonInput>,
+    mouse_wheel: EventWriter<'w, MouseWheel>,
+    mouse_motion: EventWriter<'w, MouseMotion>,
+    cursor_moved: EventWriter<'w, CursorMoved>,
+    cursor_entered: EventWriter<'w, CursorEntered>,
+    cursor_left: EventWriter<'w, CursorLeft>,
+    touches: EventWriter<'w, TouchInput>,
+    gamepad: EventWriter<'w, GamepadEvent>,
+    button_axis: ResMut<'w, Axis<GamepadButton>>,
+}
+
+impl InputInjector<'_, '_> {
+    pub(super) fn inject(&mut self, input: &UserInput) {
+        // Events need a window even when running headless
+        let window = self
+            .window_query
+            .get_single()
+            .map(|(entity, _)| entity)
+            .unwrap_or(Entity::PLACEHOLDER);
+
+        match input {
+            UserInput::KeyPress(key) => {
+                self.keyboard.send(KeyboardInput {
+                    key_code: *key,
+                    logical_key: Key::Unidentified(NativeKey::Unidentified),
+                    state: ButtonState::Pressed,
+                    window,
+                });
+            }
+            UserInput::KeyRelese(key) => {
+                self.keyboard.send(KeyboardInput {
+                    key_code: *key,
+                    logical_key: Key::Unidentified(NativeKey::Unidentified),
+                    state: ButtonState::Released,
+                    window,
+                });
+            }
+            UserInput::LogicalKeyPress(key, logical_key) => {
+                self.keyboard.send(KeyboardInput {
+                    key_code: *key,
+                    logical_key: logical_key.clone(),
+                    state: ButtonState::Pressed,
+                    window,
+                });
+            }
+            UserInput::LogicalKeyRelease(key, logical_key) => {
+                self.keyboard.send(KeyboardInput {
+                    key_code: *key,
+                    logical_key: logical_key.clone(),
+                    state: ButtonState::Released,
+                    window,
+                });
+            }
+            UserInput::Character(char) => {
+                self.characters.send(ReceivedCharacter {
+                    window,
+                    char: char.as_str().into(),
+                });
+            }
+            UserInput::ImePreedit(value, cursor) => {
+                self.ime.send(Ime::Preedit {
+                    window,
+                    value: value.clone(),
+                    cursor: *cursor,
+                });
+            }
+            UserInput::ImeCommit(value) => {
+                self.ime.send(Ime::Commit {
+                    window,
+                    value: value.clone(),
+                });
+            }
+            UserInput::ImeEnabled => {
+                self.ime.send(Ime::Enabled { window });
+            }
+            UserInput::ImeDisabled => {
+                self.ime.send(Ime::Disabled { window });
+            }
+            UserInput::MouseButtonPress(button) => {
+                self.mouse_buttons.send(MouseButtonInput {
+                    button: *button,
+                    state: ButtonState::Pressed,
+                    window,
+                });
+            }
+            UserInput::MouseButtonRelease(button) => {
+                self.mouse_buttons.send(MouseButtonInput {
+                    button: *button,
+                    state: ButtonState::Released,
+                    window,
+                });
+            }
+            UserInput::ControllerButtonPress(button) => {
+                self.change_button(*button, 1.0);
+            }
+            UserInput::ControllerButtonRelease(button) => {
+                self.change_button(*button, 0.0);
+            }
+            UserInput::ControllerButtonChange(button, value) => {
+                self.change_button(*button, *value);
+            }
+            UserInput::ControllerAxisChange(key, value) => {
+                self.gamepad
+                    .send(GamepadEvent::Axis(GamepadAxisChangedEvent {
+                        gamepad: key.gamepad,
+                        value: *value,
+                        axis_type: key.axis_type,
+                    }));
+            }
+            UserInput::MouseScroll(scroll) => {
+                self.mouse_wheel.send(MouseWheel { window, ..*scroll });
+            }
+            UserInput::MouseMove(delta, position) => {
+                self.mouse_motion.send(MouseMotion { delta: *delta });
+                self.move_cursor(window, *position);
+            }
+            UserInput::Touch(phase, id, position, force) => {
+                self.touches.send(TouchInput {
+                    phase: *phase,
+                    position: *position,
+                    window,
+                    force: *force,
+                    id: *id,
+                });
+            }
+            UserInput::ControllerConnect(gamepad, name) => {
+                self.gamepad
+                    .send(GamepadEvent::Connection(GamepadConnectionEvent {
+                        gamepad: *gamepad,
+                        connection: GamepadConnection::Connected(GamepadInfo {
+                            name: name.clone(),
+                        }),
+                    }));
+            }
+            UserInput::ControllerDisconnect(gamepad) => {
+                self.gamepad
+                    .send(GamepadEvent::Connection(GamepadConnectionEvent {
+                        gamepad: *gamepad,
+                        connection: GamepadConnection::Disconnected,
+                    }));
+            }
+            UserInput::Marker(_) | UserInput::Quit => {}
+        }
+    }
+
+    // Like gilrs, the analog value is written directly, bevy only uses the event for `ButtonInput`
+    fn change_button(&mut self, button: GamepadButton, value: f32) {
+        self.button_axis.set(button, value);
+        self.gamepad
+            .send(GamepadEvent::Button(GamepadButtonChangedEvent {
+                value,
+                button_type: button.button_type,
+                gamepad: button.gamepad,
+            }));
+    }
+
+    // Mimics what winit does when the cursor moves over, into or out of the window
+    fn move_cursor(&mut self, window: Entity, position: Option<Vec2>) {
+        let previous = self.scripted_cursor.0;
+        if position == previous {
+            return;
+        }
+
+        match position {
+            Some(position) => {
+                if previous.is_none() {
+                    self.cursor_entered.send(CursorEntered { window });
+                }
+
+                self.cursor_moved.send(CursorMoved {
+                    window,
+                    position,
+                    delta: previous.map(|previous| position - previous),
+                });
+            }
+            None => {
+                self.cursor_left.send(CursorLeft { window });
+            }
+        }
+
+        self.scripted_cursor.0 = position;
+        if let Ok((_, ref mut window)) = self.window_query.get_single_mut() {
+            window.set_cursor_position(position);
+        }
+    }
+}
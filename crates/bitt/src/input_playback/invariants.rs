@@ -0,0 +1,118 @@
+use bevy::{
+    core::FrameCount, prelude::*, render::view::screenshot::ScreenshotManager,
+    window::PrimaryWindow,
+};
+
+use crate::headless_default_plugins::VirtualWindow;
+
+use super::{
+    artefact_paths::ArtefactPaths,
+    playback::{fail_early, StartAsserting},
+    StartTime,
+};
+
+/// Adds checks that have to hold on every frame of a playback.
+pub trait InvariantAppExt {
+    /// Adds a check like `add_invariant("score never goes negative", |score: Res<Score>| score.0 >= 0)`.
+    /// The check is a system that returns true while the invariant holds, and runs in `PostUpdate` on every frame
+    /// of a playback. The first time one returns false, the test fails right away with the name of the invariant
+    /// and the frame number as the reason, and a screenshot of that frame is saved to the artefacts.
+    /// Invariants aren't checked while recording.
+    fn add_invariant<M>(
+        &mut self,
+        name: impl Into<String>,
+        check: impl IntoSystem<(), bool, M>,
+    ) -> &mut Self;
+}
+
+impl InvariantAppExt for App {
+    fn add_invariant<M>(
+        &mut self,
+        name: impl Into<String>,
+        check: impl IntoSystem<(), bool, M>,
+    ) -> &mut Self {
+        let name = name.into();
+        self.add_systems(
+            PostUpdate,
+            check
+                .pipe(
+                    move |In(holds): In<bool>, mut invariants: ResMut<InvariantCheck>| {
+                        if !holds {
+                            invariants.broken.push(name.clone());
+                        }
+                    },
+                )
+                .in_set(CheckInvariants)
+                .run_if(checking_invariants),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemSet)]
+pub(super) struct CheckInvariants;
+
+/// The first invariant that didn't hold during playback.
+#[derive(Debug, Default, Resource)]
+pub(super) struct InvariantCheck {
+    // The ones that didn't hold on this frame, in the order they were checked
+    broken: Vec<String>,
+    violation: Option<String>,
+    // Set until the screenshot of the violation could be requested
+    screenshot_pending: bool,
+}
+
+impl InvariantCheck {
+    pub(super) fn violation(&self) -> Option<&str> {
+        self.violation.as_deref()
+    }
+}
+
+fn checking_invariants(
+    invariants: Option<Res<InvariantCheck>>,
+    start_time: Option<Res<StartTime>>,
+) -> bool {
+    invariants.is_some_and(|invariants| invariants.violation.is_none()) && start_time.is_some()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn report_violation(
+    mut invariants: ResMut<InvariantCheck>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    start_time: Option<Res<StartTime>>,
+    main_window: Query<Entity, (With<PrimaryWindow>, Without<VirtualWindow>)>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    path: Res<ArtefactPaths>,
+    mut start_events: EventWriter<StartAsserting>,
+) {
+    let broken: Vec<String> = invariants.broken.drain(..).collect();
+    if let (Some(name), Some(start_time), None) =
+        (broken.first(), start_time, &invariants.violation)
+    {
+        let violation = format!(
+            "`{}` didn't hold on frame {}, {:.3}s in",
+            name,
+            frame_count.0.wrapping_sub(start_time.frame),
+            (time.elapsed() - start_time.time).as_secs_f32()
+        );
+        fail_early("Invariant broken", &violation, &mut start_events);
+        invariants.violation = Some(violation);
+        invariants.screenshot_pending = true;
+    }
+
+    if !invariants.screenshot_pending {
+        return;
+    }
+    let Ok(win) = main_window.get_single() else {
+        invariants.screenshot_pending = false;
+        return;
+    };
+    // Only one screenshot can be requested per frame, so this is tried again on the next one if the screenshot
+    // before the assert window was taken on this frame
+    if screenshot_manager
+        .save_screenshot_to_disk(win, path.violation_screenshot())
+        .is_ok()
+    {
+        invariants.screenshot_pending = false;
+    }
+}
@@ -0,0 +1,335 @@
+use std::{fs, time::Duration};
+
+use bevy::{core::FrameCount, prelude::*};
+
+use crate::{PlaybackTestingOptions, TestWrangler};
+
+use super::{
+    artefact_paths::ArtefactPaths, StartTime, TestQuitEvent, TestScript, Timestamp, UserInput,
+};
+
+pub(super) struct MarkerPlugin;
+
+impl Plugin for MarkerPlugin {
+    fn build(&self, app: &mut App) {
+        let expected = app
+            .world
+            .resource::<TestScript>()
+            .events
+            .iter()
+            .filter_map(|(timestamp, input)| match input {
+                UserInput::Marker(name) => Some((*timestamp, name.clone())),
+                _ => None,
+            })
+            .collect();
+
+        // Markers reached when the script has none are reported in the unit of the inputs
+        let unit = app
+            .world
+            .resource::<TestScript>()
+            .events
+            .first()
+            .map_or(Timestamp::Time(Duration::ZERO), |(timestamp, _)| *timestamp);
+
+        app.insert_resource(MarkerCheck {
+            expected,
+            unit,
+            reached_at: vec![],
+            due_at: vec![],
+            problem: None,
+        })
+        .add_systems(PostUpdate, check_markers)
+        .add_systems(
+            Last,
+            write_marker_report.run_if(on_event::<TestQuitEvent>()),
+        );
+    }
+}
+
+/// Compares the markers the game reaches to the ones in the script.
+/// They have to be reached in order, so each one is compared when it is reached and when the script gets to it,
+/// whichever of the two comes last.
+#[derive(Debug, Resource)]
+pub(super) struct MarkerCheck {
+    expected: Vec<(Timestamp, String)>,
+    unit: Timestamp,
+    // Real time of when the first n markers were reached by the game and by the script
+    reached_at: Vec<Duration>,
+    due_at: Vec<Duration>,
+    problem: Option<String>,
+}
+
+impl MarkerCheck {
+    /// The first marker that was missed, early or late, if any.
+    pub(super) fn problem(&self) -> Option<&str> {
+        self.problem.as_deref()
+    }
+
+    /// Called when the test passes, the check fails if a marker the script got to hasn't been reached yet.
+    /// The ones after the end of the script are only in the recording if they came during the assert window,
+    /// so those can be missed.
+    pub(super) fn finish(&mut self) {
+        if self.problem.is_none() && self.due_at.len() > self.reached_at.len() {
+            let (timestamp, name) = &self.expected[self.reached_at.len()];
+            self.fail(format!(
+                "`{}` was never reached, it was recorded at {}",
+                name, timestamp
+            ));
+        }
+    }
+
+    fn reach(
+        &mut self,
+        name: &str,
+        now: Duration,
+        position: (Duration, u32),
+    ) -> Result<(), String> {
+        let index = self.reached_at.len();
+        let Some((timestamp, expected)) = self.expected.get(index) else {
+            return Err(format!(
+                "reached `{}` at {}, but the recording has no more markers",
+                name,
+                like(self.unit, position)
+            ));
+        };
+
+        if expected != name {
+            return Err(format!(
+                "reached `{}` at {}, but `{}` was recorded next at {}",
+                name,
+                like(*timestamp, position),
+                expected,
+                timestamp
+            ));
+        }
+
+        self.reached_at.push(now);
+        Ok(())
+    }
+
+    fn advance(
+        &mut self,
+        now: Duration,
+        position: (Duration, u32),
+        tolerance: Duration,
+    ) -> Result<(), String> {
+        while let Some((timestamp, name)) = self.expected.get(self.due_at.len()) {
+            if !timestamp.reached(position.0, position.1) {
+                break;
+            }
+
+            let index = self.due_at.len();
+            self.due_at.push(now);
+            if let Some(reached_at) = self.reached_at.get(index) {
+                if now - *reached_at > tolerance {
+                    return Err(format!(
+                        "reached `{}` {:.3}s too early, it was recorded at {}",
+                        name,
+                        (now - *reached_at).as_secs_f32(),
+                        timestamp
+                    ));
+                }
+            }
+        }
+
+        if let Some(due_at) = self.due_at.get(self.reached_at.len()) {
+            if now - *due_at > tolerance {
+                let (timestamp, name) = &self.expected[self.reached_at.len()];
+                return Err(format!(
+                    "`{}` wasn't reached within {:.3}s of {}, when it was recorded",
+                    name,
+                    tolerance.as_secs_f32(),
+                    timestamp
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fail(&mut self, problem: String) {
+        println!("Marker check failed: {}", problem);
+        self.problem = Some(problem);
+    }
+}
+
+fn check_markers(
+    mut check: ResMut<MarkerCheck>,
+    mut wrangler: ResMut<TestWrangler>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    options: Res<PlaybackTestingOptions>,
+    start_time: Option<Res<StartTime>>,
+) {
+    let marks: Vec<String> = wrangler.marks.drain(..).collect();
+    let Some(start_time) = start_time else {
+        return;
+    };
+
+    // Only the first problem is reported, the ones after it are usually caused by it
+    if check.problem.is_some() {
+        return;
+    }
+
+    let now = time.elapsed();
+    let position = start_time.script_position(&time, &frame_count, options.playback_rate);
+    let tolerance = real_tolerance(&options);
+
+    let result = marks
+        .iter()
+        .try_for_each(|name| check.reach(name, now, position))
+        .and_then(|()| check.advance(now, position, tolerance));
+
+    if let Err(problem) = result {
+        check.fail(problem);
+    }
+}
+
+// The tolerance is in script time, which passes faster than real time when playing back faster
+fn real_tolerance(options: &PlaybackTestingOptions) -> Duration {
+    Duration::from_secs_f32(options.marker_tolerance / options.playback_rate)
+}
+
+fn write_marker_report(check: Res<MarkerCheck>, path: Res<ArtefactPaths>) {
+    if check.expected.is_empty() && check.problem.is_none() {
+        return;
+    }
+
+    let report = match &check.problem {
+        Some(problem) => format!("{}\n", problem),
+        None => format!(
+            "All {} markers were reached on time\n",
+            check.expected.len()
+        ),
+    };
+    fs::write(path.markers(), report).unwrap();
+}
+
+// Playback position in the same unit as the script
+fn like(timestamp: Timestamp, (elapsed, frames): (Duration, u32)) -> Timestamp {
+    match timestamp {
+        Timestamp::Time(_) => Timestamp::Time(elapsed),
+        Timestamp::Frame(_) => Timestamp::Frame(frames),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Duration = Duration::from_millis(500);
+
+    fn check(markers: &[(u64, &str)]) -> MarkerCheck {
+        MarkerCheck {
+            expected: markers
+                .iter()
+                .map(|(millis, name)| (at(*millis), name.to_string()))
+                .collect(),
+            unit: Timestamp::Time(Duration::ZERO),
+            reached_at: vec![],
+            due_at: vec![],
+            problem: None,
+        }
+    }
+
+    fn at(millis: u64) -> Timestamp {
+        Timestamp::Time(Duration::from_millis(millis))
+    }
+
+    // Plays back at normal speed, so real time and script time are the same
+    fn position(millis: u64) -> (Duration, (Duration, u32)) {
+        let now = Duration::from_millis(millis);
+        (now, (now, 0))
+    }
+
+    fn reach(check: &mut MarkerCheck, name: &str, millis: u64) -> Result<(), String> {
+        let (now, position) = position(millis);
+        check.reach(name, now, position)
+    }
+
+    fn advance(check: &mut MarkerCheck, millis: u64) -> Result<(), String> {
+        let (now, position) = position(millis);
+        check.advance(now, position, TOLERANCE)
+    }
+
+    #[test]
+    fn markers_reached_on_time_pass() {
+        let mut check = check(&[(1000, "door"), (2000, "boss")]);
+        for millis in [0, 900] {
+            advance(&mut check, millis).unwrap();
+        }
+        reach(&mut check, "door", 900).unwrap();
+        advance(&mut check, 1000).unwrap();
+        advance(&mut check, 2000).unwrap();
+        reach(&mut check, "boss", 2400).unwrap();
+        advance(&mut check, 2400).unwrap();
+
+        check.finish();
+        assert_eq!(check.problem(), None);
+    }
+
+    #[test]
+    fn markers_out_of_order_fail() {
+        let mut check = check(&[(1000, "door"), (2000, "boss")]);
+        let problem = reach(&mut check, "boss", 1000).unwrap_err();
+        assert_eq!(
+            problem,
+            "reached `boss` at 1.000s, but `door` was recorded next at 1.000s"
+        );
+
+        reach(&mut check, "door", 1000).unwrap();
+        reach(&mut check, "boss", 2000).unwrap();
+        let problem = reach(&mut check, "door", 3000).unwrap_err();
+        assert_eq!(
+            problem,
+            "reached `door` at 3.000s, but the recording has no more markers"
+        );
+    }
+
+    #[test]
+    fn markers_outside_the_tolerance_fail() {
+        let mut early = check(&[(1000, "door")]);
+        reach(&mut early, "door", 400).unwrap();
+        let problem = advance(&mut early, 1000).unwrap_err();
+        assert!(problem.contains("0.600s too early"), "{}", problem);
+
+        let mut late = check(&[(1000, "door")]);
+        advance(&mut late, 1000).unwrap();
+        advance(&mut late, 1500).unwrap();
+        let problem = advance(&mut late, 1600).unwrap_err();
+        assert_eq!(
+            problem,
+            "`door` wasn't reached within 0.500s of 1.000s, when it was recorded"
+        );
+    }
+
+    #[test]
+    fn finish_fails_for_markers_the_script_got_to() {
+        let mut missed = check(&[(1000, "door"), (2000, "boss")]);
+        advance(&mut missed, 1000).unwrap();
+        missed.finish();
+        assert_eq!(
+            missed.problem(),
+            Some("`door` was never reached, it was recorded at 1.000s")
+        );
+
+        // The script never got to `boss`, so it's fine that the game didn't either
+        let mut ended = check(&[(1000, "door"), (2000, "boss")]);
+        reach(&mut ended, "door", 1000).unwrap();
+        advance(&mut ended, 1000).unwrap();
+        ended.finish();
+        assert_eq!(ended.problem(), None);
+    }
+
+    #[test]
+    fn tolerance_scales_with_the_playback_rate() {
+        let options = |playback_rate| PlaybackTestingOptions {
+            marker_tolerance: 0.5,
+            playback_rate,
+            ..default()
+        };
+        assert_eq!(real_tolerance(&options(1.0)), Duration::from_millis(500));
+        assert_eq!(real_tolerance(&options(2.0)), Duration::from_millis(250));
+        assert_eq!(real_tolerance(&options(0.5)), Duration::from_secs(1));
+    }
+}
@@ -1,35 +1,52 @@
 use std::{
-    fs::{create_dir_all, remove_dir_all},
+    fs::{create_dir_all, remove_dir_all, write},
     path::PathBuf,
     time::Duration,
 };
 
 use bevy::{
     app::AppExit,
+    core::FrameCount,
     input::{
         gamepad::{
-            GamepadAxisChangedEvent, GamepadButtonChangedEvent, GamepadConnection,
-            GamepadConnectionEvent, GamepadEvent, GamepadInfo,
+            gamepad_event_system, GamepadConnection, GamepadConnectionEvent, GamepadEvent,
+            GamepadInfo,
         },
-        mouse::{MouseMotion, MouseWheel},
+        keyboard::keyboard_input_system,
+        mouse::mouse_button_input_system,
+        touch::touch_screen_input_system,
         InputSystem,
     },
     prelude::*,
     render::view::screenshot::ScreenshotManager,
+    time::TimeUpdateStrategy,
     utils::HashSet,
-    window::PrimaryWindow,
+    window::{PresentMode, PrimaryWindow},
+    winit::{UpdateMode, WinitSettings},
 };
 
-use crate::{PlaybackTestingOptions, TestWrangler};
+use crate::{headless_default_plugins::VirtualWindow, PlaybackTestingOptions, TestWrangler};
 
-use super::{artefact_paths::ArtefactPaths, StartTime, TestQuitEvent, TestScript, UserInput};
+use super::{
+    artefact_paths::ArtefactPaths,
+    assertions::write_assertion_report,
+    expectations::{close_windows, update_position, CheckExpectations, ExpectationCheck},
+    injection::{
+        mask_hardware_input, remember_button_values, remember_event_counts, InputInjector,
+        ScriptedButtonValues, ScriptedCursor,
+    },
+    invariants::{report_violation, CheckInvariants, InvariantCheck},
+    markers::{MarkerCheck, MarkerPlugin},
+    Outcome, StartTime, TestQuitEvent, TestScript, UserInput,
+};
 
 #[derive(Debug, Clone, Copy, Event)]
-struct StartAsserting;
+pub(super) struct StartAsserting;
 
 pub(crate) struct PlaybackPlugin {
     pub(crate) script: TestScript,
     pub(crate) artefact_path: PathBuf,
+    pub(crate) fixed_timestep: Option<f32>,
 }
 
 impl Plugin for PlaybackPlugin {
@@ -37,37 +54,95 @@ impl Plugin for PlaybackPlugin {
         // This is a bit wonky, as it depends on the order the plugins get added
         let running_headless = app
             .world
-            .query::<&PrimaryWindow>()
+            .query_filtered::<(), (With<PrimaryWindow>, Without<VirtualWindow>)>()
             .iter(&app.world)
             .next()
             .is_none();
 
+        // Scripted inputs are sent as the same events the hardware would produce, so they go
+        // through the bevy input systems after the real ones have been masked out.
         app.insert_resource(self.script.clone())
+            .init_resource::<ScriptedCursor>()
+            .init_resource::<ScriptedButtonValues>()
+            .add_systems(
+                PreUpdate,
+                (
+                    mask_hardware_input.run_if(isolating_input),
+                    connect_pads,
+                    script_player,
+                )
+                    .chain()
+                    .in_set(InputSystem)
+                    .before(keyboard_input_system)
+                    .before(mouse_button_input_system)
+                    .before(gamepad_event_system)
+                    .before(touch_screen_input_system),
+            )
             .add_systems(
                 PreUpdate,
-                (connect_pads, script_player).chain().after(InputSystem),
+                (remember_button_values, remember_event_counts)
+                    .run_if(isolating_input)
+                    .after(InputSystem),
             )
             .insert_resource(ArtefactPaths {
                 base: self.artefact_path.clone(),
                 running_headless,
             })
+            .add_systems(Startup, create_artefact_dir)
             .add_event::<StartAsserting>()
             .add_event::<TestQuitEvent>()
             .add_systems(
                 Update,
                 (
-                    create_artefact_dir.run_if(on_event::<StartAsserting>()),
                     pre_assert_screenshot.run_if(on_event::<StartAsserting>()),
                     post_assert_screenshot.run_if(on_event::<TestQuitEvent>()),
                     run_asserts,
                     delayed_exit,
                 )
                     .chain(),
+            )
+            .add_plugins(MarkerPlugin)
+            .init_resource::<InvariantCheck>()
+            .add_systems(PostUpdate, report_violation.after(CheckInvariants))
+            .init_resource::<ExpectationCheck>()
+            .add_systems(
+                PostUpdate,
+                (
+                    update_position.before(CheckExpectations),
+                    close_windows.after(CheckExpectations),
+                ),
+            )
+            .add_systems(
+                Last,
+                write_assertion_report.run_if(on_event::<TestQuitEvent>()),
             );
+
+        if let Some(timestep) = self.fixed_timestep {
+            app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+                timestep,
+            )))
+            .insert_resource(WinitSettings {
+                focused_mode: UpdateMode::Continuous,
+                unfocused_mode: UpdateMode::Continuous,
+            })
+            .add_systems(Startup, disable_vsync);
+        }
     }
 }
 
+// Vsync would cap the update rate to the refresh rate of the monitor
+fn disable_vsync(mut window_query: Query<&mut Window, With<PrimaryWindow>>) {
+    if let Ok(mut window) = window_query.get_single_mut() {
+        window.present_mode = PresentMode::AutoNoVsync;
+    }
+}
+
+fn isolating_input(options: Res<PlaybackTestingOptions>) -> bool {
+    options.isolate_input
+}
+
 // The point of this is to fake that the pads being used by the inputs are connected.
+// Only needed for old scripts, newer ones contain the connections.
 fn connect_pads(
     script: Res<TestScript>,
     mut events: EventWriter<GamepadEvent>,
@@ -77,119 +152,95 @@ fn connect_pads(
         return;
     }
 
-    for pad in script
+    *done = true;
+
+    for pad in unconnected_pads(&script) {
+        events.send(GamepadEvent::Connection(GamepadConnectionEvent {
+            gamepad: pad,
+            connection: GamepadConnection::Connected(GamepadInfo {
+                name: "Test Pad".to_string(),
+            }),
+        }));
+    }
+}
+
+// Scripts recorded before connections were recorded use pads without connecting them
+fn unconnected_pads(script: &TestScript) -> HashSet<Gamepad> {
+    if script
+        .events
+        .iter()
+        .any(|(_, input)| matches!(input, UserInput::ControllerConnect(..)))
+    {
+        return HashSet::new();
+    }
+
+    script
         .events
         .iter()
         .filter_map(|(_, input)| match input {
             UserInput::ControllerAxisChange(axis, _) => Some(axis.gamepad),
             UserInput::ControllerButtonPress(button) => Some(button.gamepad),
             UserInput::ControllerButtonRelease(button) => Some(button.gamepad),
+            UserInput::ControllerButtonChange(button, _) => Some(button.gamepad),
+            UserInput::ControllerDisconnect(pad) => Some(*pad),
             _ => None,
         })
-        .collect::<HashSet<_>>()
-        .into_iter()
-    {
-        events.send(GamepadEvent::Connection(GamepadConnectionEvent {
-            gamepad: pad,
-            connection: GamepadConnection::Connected(GamepadInfo {
-                name: "Test Pad".to_string(), // TODO: Save the name in the script if it turns out to be relevant
-            }),
-        }));
-    }
-
-    *done = true;
+        .collect()
 }
 
 #[allow(clippy::too_many_arguments)]
 fn script_player(
-    mut last_run: Local<Duration>,
+    mut next_event: Local<usize>,
     time: Res<Time<Real>>,
-    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+    frame_count: Res<FrameCount>,
     script: Res<TestScript>,
+    options: Res<PlaybackTestingOptions>,
     mut quit_events: EventWriter<StartAsserting>,
-    mut kb_input: ResMut<ButtonInput<KeyCode>>,
-    mut mouse_buttons: ResMut<ButtonInput<MouseButton>>,
-    mut pad_buttons: ResMut<ButtonInput<GamepadButton>>,
-    mut axis: ResMut<Axis<GamepadAxis>>,
-    mut mouse_scroll: EventWriter<MouseWheel>,
-    mut mouse_movements: EventWriter<MouseMotion>,
     first_update: Option<Res<StartTime>>,
-    mut gamepad_event_writer: EventWriter<GamepadEvent>,
+    mut injector: InputInjector,
 ) {
     let Some(start_time) = first_update else {
         return;
     };
 
-    for ev in script
-        .events
+    let (elapsed, frames) = start_time.script_position(&time, &frame_count, options.playback_rate);
+
+    for ev in script.events[*next_event..]
         .iter()
-        .skip_while(|(event_time, _)| *event_time + start_time.0 <= *last_run)
-        .take_while(|(event_time, _)| *event_time + start_time.0 <= time.elapsed())
+        .take_while(|(timestamp, _)| timestamp.reached(elapsed, frames))
         .map(|(_, input)| input)
     {
-        match ev {
-            UserInput::KeyPress(key) => kb_input.press(*key),
-            UserInput::KeyRelese(key) => kb_input.release(*key),
-            UserInput::MouseButtonPress(button) => mouse_buttons.press(*button),
-            UserInput::MouseButtonRelease(button) => mouse_buttons.release(*button),
-            UserInput::ControllerButtonPress(button) => {
-                pad_buttons.press(*button);
-                gamepad_event_writer.send(GamepadEvent::Button(GamepadButtonChangedEvent {
-                    value: 1.0,
-                    button_type: button.button_type,
-                    gamepad: button.gamepad,
-                }));
-            }
-            UserInput::ControllerButtonRelease(button) => {
-                pad_buttons.release(*button);
-                gamepad_event_writer.send(GamepadEvent::Button(GamepadButtonChangedEvent {
-                    value: 0.0,
-                    button_type: button.button_type,
-                    gamepad: button.gamepad,
-                }));
-            }
-            UserInput::ControllerAxisChange(key, value) => {
-                axis.set(*key, *value);
-                gamepad_event_writer.send(GamepadEvent::Axis(GamepadAxisChangedEvent {
-                    gamepad: key.gamepad,
-                    value: *value,
-                    axis_type: key.axis_type,
-                }));
-            }
-            UserInput::MouseScroll(scroll) => {
-                mouse_scroll.send(*scroll);
-            }
-            UserInput::MouseMove(delta, position) => {
-                mouse_movements.send(MouseMotion { delta: *delta });
-                if let Ok(ref mut window) = window_query.get_single_mut() {
-                    window.set_cursor_position(*position);
-                }
-            }
-            UserInput::Quit => {
-                quit_events.send(StartAsserting);
-            }
+        *next_event += 1;
+
+        if let UserInput::Quit = ev {
+            quit_events.send(StartAsserting);
+        } else {
+            injector.inject(ev);
         }
     }
-
-    *last_run = time.elapsed();
 }
 
-fn create_artefact_dir(path: Res<ArtefactPaths>, mut has_ran: Local<bool>) {
-    if *has_ran {
-        return;
-    }
+/// Prints the problem that failed the test, like `Invariant broken: ...`, and starts the assert window.
+pub(super) fn fail_early(
+    heading: &str,
+    problem: &str,
+    start_events: &mut EventWriter<StartAsserting>,
+) {
+    println!("{}: {}", heading, problem);
+    // The test has failed already, so there's no need to wait for the script to end
+    start_events.send(StartAsserting);
+}
 
+fn create_artefact_dir(path: Res<ArtefactPaths>) {
     if path.base.exists() {
         remove_dir_all(path.base.clone()).unwrap();
     }
 
     create_dir_all(path.base.clone()).unwrap();
-
-    *has_ran = true;
 }
 
 fn pre_assert_screenshot(
-    main_window: Query<Entity, With<PrimaryWindow>>,
+    main_window: Query<Entity, (With<PrimaryWindow>, Without<VirtualWindow>)>,
     mut screenshot_manager: ResMut<ScreenshotManager>,
     path: Res<ArtefactPaths>,
     mut has_ran: Local<bool>,
@@ -208,7 +259,7 @@ fn pre_assert_screenshot(
 }
 
 fn post_assert_screenshot(
-    main_window: Query<Entity, With<PrimaryWindow>>,
+    main_window: Query<Entity, (With<PrimaryWindow>, Without<VirtualWindow>)>,
     mut screenshot_manager: ResMut<ScreenshotManager>,
     path: Res<ArtefactPaths>,
     mut has_ran: Local<bool>,
@@ -226,24 +277,90 @@ fn post_assert_screenshot(
     *has_ran = true;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_asserts(
     mut start_events: EventReader<StartAsserting>,
     mut result_writer: EventWriter<TestQuitEvent>,
     time: Res<Time<Real>>,
     asserter: Res<TestWrangler>,
     options: Res<PlaybackTestingOptions>,
+    mut markers: ResMut<MarkerCheck>,
+    invariants: Res<InvariantCheck>,
+    mut expectations: ResMut<ExpectationCheck>,
+    script: Res<TestScript>,
     mut started: Local<Option<Timer>>,
 ) {
-    if let Some(ref mut start_time) = *started {
-        if asserter.outcome == Some(true) {
-            result_writer.send(TestQuitEvent(true));
-            *started = None;
-        } else if asserter.outcome == Some(false) || start_time.tick(time.delta()).just_finished() {
-            result_writer.send(TestQuitEvent(false));
-            *started = None;
+    if let Some(ref mut timer) = *started {
+        // Scripts recorded to expect a failure pass when the test fails, and fail when it passes
+        let expect_failure = script.expect_failure;
+        if asserter.outcome == Some(!expect_failure) {
+            // Markers the script got to but the game didn't are only found out once the test passes,
+            // as are expectations whose window hadn't closed yet
+            markers.finish();
+            expectations.finish();
         }
+
+        let reason = asserter.reason.clone();
+        // Broken invariants, expectations and markers fail the test even if it is expected to fail
+        let problem = invariants
+            .violation()
+            .map(|violation| format!("invariant broken, {}", violation))
+            .or_else(|| {
+                expectations
+                    .problem()
+                    .map(|problem| format!("expectation failed, {}", problem))
+            })
+            .or_else(|| {
+                markers
+                    .problem()
+                    .map(|problem| format!("marker check failed, {}", problem))
+            });
+
+        let (outcome, reason) = match (problem, asserter.outcome) {
+            (Some(problem), _) => (Outcome::Failed, Some(problem)),
+            (None, Some(passed)) if passed != expect_failure => (
+                Outcome::Passed,
+                if expect_failure {
+                    Some(with_reason("failed as expected", reason))
+                } else {
+                    reason
+                },
+            ),
+            (None, Some(_)) => (
+                Outcome::Failed,
+                if expect_failure {
+                    Some(with_reason(
+                        "passed, but the script expects it to fail",
+                        reason,
+                    ))
+                } else {
+                    reason
+                },
+            ),
+            (None, None) if timer.tick(time.delta()).just_finished() => (
+                Outcome::TimedOut,
+                Some(format!(
+                    "nothing passed or failed the test in the {:.1}s assert window",
+                    timer.duration().as_secs_f32()
+                )),
+            ),
+            (None, None) => return,
+        };
+
+        result_writer.send(TestQuitEvent::new(outcome, reason));
+        *started = None;
     } else if start_events.read().next().is_some() {
-        *started = Some(Timer::from_seconds(options.assert_window, TimerMode::Once));
+        *started = Some(Timer::from_seconds(
+            options.assert_window / options.playback_rate,
+            TimerMode::Once,
+        ));
+    }
+}
+
+fn with_reason(text: &str, reason: Option<String>) -> String {
+    match reason {
+        Some(reason) => format!("{}, {}", text, reason),
+        None => text.to_string(),
     }
 }
 
@@ -251,20 +368,56 @@ fn run_asserts(
 fn delayed_exit(
     mut quit_events: ResMut<Events<AppExit>>,
     mut custom_quit_events: EventReader<TestQuitEvent>,
-    mut result: Local<Option<bool>>,
+    mut result: Local<Option<TestQuitEvent>>,
     artefacts: Res<ArtefactPaths>,
 ) {
-    if let Some(passed) = *result {
+    if let Some(ref result) = *result {
         if artefacts.saved() {
-            if passed {
-                println!("Test passed");
+            if result.passed() {
+                println!("Test {}", result);
                 quit_events.send(AppExit);
             } else {
                 // TODO: figure out a nicer way to fail the test
-                panic!("Test failed");
+                panic!("Test {}", result);
             }
         }
-    } else if !custom_quit_events.is_empty() {
-        *result = Some(custom_quit_events.read().next().unwrap().0);
+    } else if let Some(quit_event) = custom_quit_events.read().next() {
+        write(artefacts.outcome(), format!("{}\n", quit_event)).unwrap();
+        *result = Some(quit_event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_playback::Timestamp;
+
+    #[test]
+    fn connects_pads_the_script_uses() {
+        let pad = Gamepad::new(2);
+        let button = GamepadButton::new(pad, GamepadButtonType::RightTrigger2);
+        let script = TestScript {
+            events: vec![
+                (
+                    Timestamp::Frame(1),
+                    UserInput::ControllerButtonChange(button, 0.5),
+                ),
+                (
+                    Timestamp::Frame(2),
+                    UserInput::ControllerButtonChange(button, 0.0),
+                ),
+                (
+                    Timestamp::Frame(3),
+                    UserInput::ControllerDisconnect(Gamepad::new(3)),
+                ),
+            ],
+            ..default()
+        };
+        assert_eq!(
+            unconnected_pads(&script),
+            HashSet::from([pad, Gamepad::new(3)])
+        );
+
+        assert!(unconnected_pads(&TestScript::example()).is_empty());
     }
 }
@@ -1,90 +1,252 @@
-use std::{
-    fs::{create_dir_all, File},
-    path::PathBuf,
-};
+use std::{fs::rename, path::PathBuf};
 
 use bevy::{
     app::AppExit,
-    input::mouse::{MouseMotion, MouseWheel},
+    core::FrameCount,
+    ecs::system::SystemParam,
+    input::{
+        gamepad::{GamepadButtonChangedEvent, GamepadConnection, GamepadConnectionEvent},
+        keyboard::KeyboardInput,
+        mouse::{MouseMotion, MouseWheel},
+        ButtonState, InputSystem,
+    },
     prelude::*,
     utils::HashMap,
     window::PrimaryWindow,
 };
 
-use crate::TestWrangler;
-
-use super::{StartTime, TestScript, UserInput};
+use crate::{PlaybackTestingOptions, TestWrangler};
 
-#[derive(Debug, Clone, Copy, Event)]
-struct SaveQuitEvent;
+use super::{
+    controls::{
+        recording_controls, spawn_indicator, update_indicator, RecordingPause, SaveQuitEvent,
+    },
+    extending::Replay,
+    write_script, StartTime, TestScript, Timestamp, UserInput,
+};
 
 #[derive(Debug, Clone, Resource)]
 struct ScriptPath(PathBuf);
 
 pub(crate) struct RecordingPlugin {
     pub(crate) script_path: PathBuf,
+    // The replayed part when extending a script, new inputs are added after it
+    pub(crate) script: TestScript,
 }
 
 impl Plugin for RecordingPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(TestScript::default())
-            .add_systems(First, (script_recorder, recording_asserter).chain())
+        app.insert_resource(self.script.clone())
+            .init_resource::<RecordingPause>()
+            // Recorded after the input systems, so the inputs land on the frame the game saw them on
+            .add_systems(
+                PreUpdate,
+                (
+                    recording_controls,
+                    record_keyboard,
+                    record_text,
+                    record_mouse,
+                    record_touch,
+                    record_gamepads,
+                )
+                    .chain()
+                    .after(InputSystem),
+            )
+            .add_systems(First, recording_asserter)
+            .add_systems(Startup, spawn_indicator)
+            .add_systems(Update, update_indicator)
             .add_event::<SaveQuitEvent>()
             .insert_resource(ScriptPath(self.script_path.clone()))
-            .add_systems(PostUpdate, save_script.run_if(on_event::<SaveQuitEvent>()));
+            .add_systems(
+                PostUpdate,
+                (
+                    record_markers,
+                    save_script.run_if(on_event::<SaveQuitEvent>()),
+                )
+                    .chain(),
+            );
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn script_recorder(
-    mut script: ResMut<TestScript>,
-    time: Res<Time<Real>>,
-    first_update: Option<Res<StartTime>>,
-    input: Res<ButtonInput<KeyCode>>,
+/// Stamps inputs and adds them to the script being recorded.
+#[derive(SystemParam)]
+struct Recorder<'w> {
+    script: ResMut<'w, TestScript>,
+    time: Res<'w, Time<Real>>,
+    frame_count: Res<'w, FrameCount>,
+    options: Res<'w, PlaybackTestingOptions>,
+    start_time: Option<Res<'w, StartTime>>,
+    replay: Option<Res<'w, Replay>>,
+    pause: Res<'w, RecordingPause>,
+}
+
+impl Recorder<'_> {
+    fn recording(&self) -> bool {
+        self.start_time.is_some() && replay_done(&self.replay) && !self.pause.is_paused()
+    }
+
+    fn timestamp(&self) -> Option<Timestamp> {
+        self.start_time.as_ref().map(|start_time| {
+            start_time.timestamp(&self.time, &self.frame_count, self.options.frame_indexed)
+        })
+    }
+
+    fn record(&mut self, input: UserInput) {
+        if !self.recording() {
+            return;
+        }
+
+        if let Some(timestamp) = self.timestamp() {
+            self.script.events.push((timestamp, input));
+        }
+    }
+}
+
+// Only set when extending a script
+fn replay_done(replay: &Option<Res<Replay>>) -> bool {
+    match replay {
+        Some(replay) => replay.done,
+        None => true,
+    }
+}
+
+// Events are cleared while not recording, so that the ones sent before don't get recorded once it starts.
+// Read from the events instead of `ButtonInput`, as that is where the logical keys and key repeats are
+fn record_keyboard(mut recorder: Recorder, mut keyboard_evr: EventReader<KeyboardInput>) {
+    if !recorder.recording() {
+        keyboard_evr.clear();
+        return;
+    }
+
+    let controls = &recorder.options.recording_controls;
+    let events: Vec<_> = keyboard_evr
+        .read()
+        .filter(|event| !controls.is_hotkey(event.key_code))
+        .collect();
+
+    for event in events {
+        let key = event.key_code;
+        let logical_key = event.logical_key.clone();
+
+        recorder.record(match event.state {
+            ButtonState::Pressed => UserInput::LogicalKeyPress(key, logical_key),
+            ButtonState::Released => UserInput::LogicalKeyRelease(key, logical_key),
+        });
+    }
+}
+
+fn record_text(
+    mut recorder: Recorder,
+    mut character_evr: EventReader<ReceivedCharacter>,
+    mut ime_evr: EventReader<Ime>,
+) {
+    if !recorder.recording() {
+        character_evr.clear();
+        ime_evr.clear();
+        return;
+    }
+
+    for event in character_evr.read() {
+        recorder.record(UserInput::Character(event.char.to_string()));
+    }
+
+    for event in ime_evr.read() {
+        recorder.record(match event {
+            Ime::Preedit { value, cursor, .. } => UserInput::ImePreedit(value.clone(), *cursor),
+            Ime::Commit { value, .. } => UserInput::ImeCommit(value.clone()),
+            Ime::Enabled { .. } => UserInput::ImeEnabled,
+            Ime::Disabled { .. } => UserInput::ImeDisabled,
+        });
+    }
+}
+
+fn record_mouse(
+    mut recorder: Recorder,
     mouse_buttons: Res<ButtonInput<MouseButton>>,
-    pad_buttons: Res<ButtonInput<GamepadButton>>,
-    axis: Res<Axis<GamepadAxis>>,
     mut scroll_evr: EventReader<MouseWheel>,
     mut motion_evr: EventReader<MouseMotion>,
-    mut axis_cache: Local<HashMap<GamepadAxis, f32>>,
     window_query: Query<&Window, With<PrimaryWindow>>,
 ) {
-    let Some(start_time) = first_update else {
+    if !recorder.recording() {
+        scroll_evr.clear();
+        motion_evr.clear();
         return;
-    };
+    }
 
-    let timestamp = time.elapsed() - start_time.0;
+    for button in mouse_buttons.get_just_pressed() {
+        recorder.record(UserInput::MouseButtonPress(*button));
+    }
 
-    for key in input.get_just_pressed() {
-        script.events.push((timestamp, UserInput::KeyPress(*key)));
+    for button in mouse_buttons.get_just_released() {
+        recorder.record(UserInput::MouseButtonRelease(*button));
     }
 
-    for key in input.get_just_released() {
-        script.events.push((timestamp, UserInput::KeyRelese(*key)));
+    for scroll in scroll_evr.read() {
+        recorder.record(UserInput::MouseScroll(*scroll));
     }
 
-    for button in mouse_buttons.get_just_pressed() {
-        script
-            .events
-            .push((timestamp, UserInput::MouseButtonPress(*button)));
+    let window = window_query.single();
+    let cursor_pos = window.cursor_position();
+
+    for motion in motion_evr.read() {
+        recorder.record(UserInput::MouseMove(motion.delta, cursor_pos));
     }
+}
 
-    for button in mouse_buttons.get_just_released() {
-        script
-            .events
-            .push((timestamp, UserInput::MouseButtonRelease(*button)));
+fn record_touch(mut recorder: Recorder, mut touch_evr: EventReader<TouchInput>) {
+    if !recorder.recording() {
+        touch_evr.clear();
+        return;
+    }
+
+    for touch in touch_evr.read() {
+        recorder.record(UserInput::Touch(
+            touch.phase,
+            touch.id,
+            touch.position,
+            touch.force,
+        ));
+    }
+}
+
+fn record_gamepads(
+    mut recorder: Recorder,
+    gamepads: Res<Gamepads>,
+    mut connection_evr: EventReader<GamepadConnectionEvent>,
+    mut button_evr: EventReader<GamepadButtonChangedEvent>,
+    axis: Res<Axis<GamepadAxis>>,
+    mut axis_cache: Local<HashMap<GamepadAxis, f32>>,
+    mut connected_at_start: Local<bool>,
+) {
+    if !recorder.recording() {
+        connection_evr.clear();
+        button_evr.clear();
+        return;
     }
 
-    for button in pad_buttons.get_just_pressed() {
-        script
-            .events
-            .push((timestamp, UserInput::ControllerButtonPress(*button)));
+    if *connected_at_start {
+        for event in connection_evr.read() {
+            recorder.record(match &event.connection {
+                GamepadConnection::Connected(info) => {
+                    UserInput::ControllerConnect(event.gamepad, info.name.clone())
+                }
+                GamepadConnection::Disconnected => UserInput::ControllerDisconnect(event.gamepad),
+            });
+        }
+    } else {
+        // Pads plugged in before the recording started are already in `Gamepads`
+        connection_evr.clear();
+        for gamepad in gamepads.iter() {
+            let name = gamepads.name(gamepad).unwrap_or_default().to_string();
+            recorder.record(UserInput::ControllerConnect(gamepad, name));
+        }
+        *connected_at_start = true;
     }
 
-    for button in pad_buttons.get_just_released() {
-        script
-            .events
-            .push((timestamp, UserInput::ControllerButtonRelease(*button)));
+    // The events have the same analog values gilrs puts in `Axis<GamepadButton>`
+    for event in button_evr.read() {
+        let button = GamepadButton::new(event.gamepad, event.button_type);
+        recorder.record(UserInput::ControllerButtonChange(button, event.value));
     }
 
     if axis.is_changed() {
@@ -99,26 +261,16 @@ fn script_recorder(
                 .unwrap_or(true)
             {
                 axis_cache.insert(*dev, value);
-                script
-                    .events
-                    .push((timestamp, UserInput::ControllerAxisChange(*dev, value)));
+                recorder.record(UserInput::ControllerAxisChange(*dev, value));
             }
         }
     }
+}
 
-    for scroll in scroll_evr.read() {
-        script
-            .events
-            .push((timestamp, UserInput::MouseScroll(*scroll)));
-    }
-
-    let window = window_query.single();
-    let cursor_pos = window.cursor_position();
-
-    for motion in motion_evr.read() {
-        script
-            .events
-            .push((timestamp, UserInput::MouseMove(motion.delta, cursor_pos)));
+// Game code marks things in `Update`, so they get the same timestamp as the inputs of that frame
+fn record_markers(mut recorder: Recorder, mut wrangler: ResMut<TestWrangler>) {
+    for name in wrangler.marks.drain(..) {
+        recorder.record(UserInput::Marker(name));
     }
 }
 
@@ -127,35 +279,46 @@ fn recording_asserter(
     mut quit_events: EventWriter<SaveQuitEvent>,
     mut delay: Local<Option<Timer>>,
     time: Res<Time<Real>>,
+    replay: Option<Res<Replay>>,
+    pause: Res<RecordingPause>,
+    options: Res<PlaybackTestingOptions>,
 ) {
+    if !replay_done(&replay) || pause.is_paused() {
+        return;
+    }
+
+    // A failing test is what is being recorded when expecting a failure
+    let saved_outcome = Some(!options.expect_failure);
     if let Some(ref mut timer) = *delay {
         if timer.tick(time.delta()).just_finished() {
             quit_events.send(SaveQuitEvent);
         }
-    } else if asserter.outcome == Some(true) {
+    } else if asserter.outcome == saved_outcome {
         *delay = Some(Timer::from_seconds(0.2, TimerMode::Once));
     }
 }
 
 fn save_script(
-    script: Res<TestScript>,
     path: Res<ScriptPath>,
-    time: Res<Time<Real>>,
-    first_update: Option<Res<StartTime>>,
+    recorder: Recorder,
     mut quit_events: ResMut<Events<AppExit>>,
 ) {
-    let Some(start_time) = first_update else {
+    let Some(timestamp) = recorder.timestamp() else {
         return;
     };
 
-    let mut script = script.clone();
-    script
-        .events
-        .push((time.elapsed() - start_time.0, UserInput::Quit));
+    let mut script = recorder.script.clone();
+    script.events.push((timestamp, UserInput::Quit));
+    script.expect_failure = recorder.options.expect_failure;
+
+    // Only happens when re-recording or extending, the old script is kept until the next time
+    if path.0.exists() {
+        let mut backup = path.0.clone().into_os_string();
+        backup.push(".bak");
+        rename(&path.0, &backup).unwrap();
+        println!("Previous script kept in {}", backup.to_string_lossy());
+    }
 
-    let prefix = path.0.parent().unwrap();
-    create_dir_all(prefix).unwrap();
-    let file = File::create(&path.0).unwrap();
-    serde_json::to_writer(file, &script).unwrap();
+    write_script(&path.0, &script, recorder.options.script_format).unwrap();
     quit_events.send(AppExit);
 }
@@ -0,0 +1,193 @@
+use std::{
+    fs::{create_dir_all, remove_dir_all, File},
+    path::PathBuf,
+};
+
+use bevy::{app::AppExit, core::FrameCount, ecs::system::SystemParam, prelude::*};
+use serde::Serialize;
+
+use crate::{PlaybackTestingOptions, TestWrangler};
+
+use super::{Outcome, StartTime, TestQuitEvent};
+
+/// Whether the run recorded a script or played one back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Mode {
+    Record,
+    Playback,
+}
+
+/// Writes `result.json` to the artefacts at the end of every run, so CI can read the results without parsing the output.
+pub(crate) struct ResultPlugin {
+    pub(crate) case_name: String,
+    pub(crate) mode: Mode,
+    pub(crate) script_path: PathBuf,
+    pub(crate) artefact_path: PathBuf,
+}
+
+impl Plugin for ResultPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TestRun {
+            case_name: self.case_name.clone(),
+            mode: self.mode,
+            script_path: self.script_path.clone(),
+            artefact_path: self.artefact_path.clone(),
+        });
+
+        // Failed playbacks panic instead of exiting, so the result is written as soon as it is known
+        match self.mode {
+            Mode::Playback => app.add_systems(
+                Last,
+                write_playback_result.run_if(on_event::<TestQuitEvent>()),
+            ),
+            Mode::Record => {
+                app.add_systems(Last, write_recording_result.run_if(on_event::<AppExit>()))
+            }
+        };
+    }
+}
+
+#[derive(Debug, Resource)]
+struct TestRun {
+    case_name: String,
+    mode: Mode,
+    script_path: PathBuf,
+    artefact_path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct TestResult<'a> {
+    case: &'a str,
+    mode: Mode,
+    // None if a recording was saved or quit before the test passed or failed
+    outcome: Option<Outcome>,
+    reason: Option<&'a str>,
+    durations: Durations,
+    script: &'a PathBuf,
+    options: &'a PlaybackTestingOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct Durations {
+    // Seconds since the app started
+    run: f32,
+    // Seconds and frames since the test started, if it did
+    test: Option<f32>,
+    test_frames: Option<u32>,
+}
+
+impl TestRun {
+    fn write(
+        &self,
+        outcome: Option<Outcome>,
+        reason: Option<&str>,
+        durations: Durations,
+        options: &PlaybackTestingOptions,
+    ) {
+        let result = TestResult {
+            case: &self.case_name,
+            mode: self.mode,
+            outcome,
+            reason,
+            durations,
+            script: &self.script_path,
+            options,
+        };
+
+        // A recording has no other artefacts, the ones there are from an earlier playback
+        if self.mode == Mode::Record && self.artefact_path.exists() {
+            remove_dir_all(&self.artefact_path).unwrap();
+        }
+        create_dir_all(&self.artefact_path).unwrap();
+        let mut file = File::create(self.artefact_path.join("result.json")).unwrap();
+        serde_json::to_writer_pretty(&mut file, &result).unwrap();
+    }
+}
+
+/// Writes `result.json` right away, for when the run is about to panic and wouldn't get to the end of the frame.
+#[derive(SystemParam)]
+pub(crate) struct ResultWriter<'w> {
+    // Only there with a test gear
+    run: Option<Res<'w, TestRun>>,
+    options: Option<Res<'w, PlaybackTestingOptions>>,
+    time: Res<'w, Time<Real>>,
+    frame_count: Res<'w, FrameCount>,
+    start_time: Option<Res<'w, StartTime>>,
+}
+
+impl ResultWriter<'_> {
+    pub(crate) fn write(&self, passed: bool, reason: Option<&str>) {
+        let (Some(run), Some(options)) = (&self.run, &self.options) else {
+            return;
+        };
+
+        let outcome = if passed {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        };
+        run.write(
+            Some(outcome),
+            reason,
+            durations(&self.time, &self.frame_count, self.start_time.as_deref()),
+            options,
+        );
+    }
+}
+
+fn durations(
+    time: &Time<Real>,
+    frame_count: &FrameCount,
+    start_time: Option<&StartTime>,
+) -> Durations {
+    Durations {
+        run: time.elapsed().as_secs_f32(),
+        test: start_time.map(|start_time| (time.elapsed() - start_time.time).as_secs_f32()),
+        test_frames: start_time.map(|start_time| frame_count.0.wrapping_sub(start_time.frame)),
+    }
+}
+
+fn write_playback_result(
+    run: Res<TestRun>,
+    mut quit_events: EventReader<TestQuitEvent>,
+    options: Res<PlaybackTestingOptions>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    start_time: Option<Res<StartTime>>,
+) {
+    let Some(quit_event) = quit_events.read().next() else {
+        return;
+    };
+
+    run.write(
+        Some(quit_event.outcome),
+        quit_event.reason.as_deref(),
+        durations(&time, &frame_count, start_time.as_deref()),
+        &options,
+    );
+}
+
+fn write_recording_result(
+    run: Res<TestRun>,
+    wrangler: Res<TestWrangler>,
+    options: Res<PlaybackTestingOptions>,
+    time: Res<Time<Real>>,
+    frame_count: Res<FrameCount>,
+    start_time: Option<Res<StartTime>>,
+) {
+    let outcome = wrangler.outcome.map(|passed| {
+        if passed {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        }
+    });
+
+    run.write(
+        outcome,
+        wrangler.reason.as_deref(),
+        durations(&time, &frame_count, start_time.as_deref()),
+        &options,
+    );
+}
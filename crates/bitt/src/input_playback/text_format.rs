@@ -0,0 +1,629 @@
+use std::{fmt, time::Duration, vec::IntoIter};
+
+use bevy::{input::keyboard::Key, input::mouse::MouseWheel, prelude::*};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use super::{
+    ids::StableId,
+    versioning::{
+        migrate_text_line, ScriptHeader, BEVY_VERSION, FIRST_TEXT_VERSION, SCRIPT_VERSION,
+    },
+    TestScript, Timestamp, UserInput,
+};
+
+// Text scripts from before the header was added are the same as the first versioned ones
+const HEADERLESS_VERSION: u32 = FIRST_TEXT_VERSION;
+
+/// Why a text script couldn't be parsed, and where.
+#[derive(Debug)]
+pub(super) struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Writes a header line, and then one input per line, like `0.500s press KeyA "a"`.
+pub(super) fn to_text(script: &TestScript) -> String {
+    let mut text = format!("bitt-script {} bevy {}\n", SCRIPT_VERSION, BEVY_VERSION);
+    if script.expect_failure {
+        text.push_str("expect-failure\n");
+    }
+
+    for (timestamp, input) in &script.events {
+        text.push_str(&format!(
+            "{} {}\n",
+            write_timestamp(timestamp),
+            write_input(input)
+        ));
+    }
+
+    text
+}
+
+/// Reads the format written by `to_text`. Empty lines and lines starting with `#` are skipped.
+pub(super) fn from_text(text: &str) -> Result<(ScriptHeader, TestScript), ParseError> {
+    let mut header = None;
+    let mut events: Vec<(Timestamp, UserInput)> = vec![];
+    let mut expect_failure = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message| ParseError {
+            line: index + 1,
+            message,
+        };
+
+        if header.is_none() && events.is_empty() && line.starts_with("bitt-script") {
+            header = Some(parse_header(line).map_err(error)?);
+            continue;
+        }
+
+        if events.is_empty() && line == "expect-failure" {
+            expect_failure = true;
+            continue;
+        }
+
+        let version = header
+            .as_ref()
+            .map_or(HEADERLESS_VERSION, |header: &ScriptHeader| header.version);
+        let (timestamp, input) = parse_line(&migrate_text_line(version, line)).map_err(error)?;
+        if let Some((previous, _)) = events.last() {
+            check_order(previous, &timestamp).map_err(error)?;
+        }
+        events.push((timestamp, input));
+    }
+
+    let header = header.unwrap_or(ScriptHeader {
+        version: HEADERLESS_VERSION,
+        bevy: None,
+    });
+    Ok((
+        header,
+        TestScript {
+            events,
+            expect_failure,
+        },
+    ))
+}
+
+fn parse_header(line: &str) -> Result<ScriptHeader, String> {
+    let error = || {
+        format!(
+            "invalid header `{}`, expected something like `bitt-script {} bevy 0.13`",
+            line, SCRIPT_VERSION
+        )
+    };
+
+    let header = match tokenize(line)?.as_slice() {
+        [_, version] => ScriptHeader {
+            version: version.parse().map_err(|_| error())?,
+            bevy: None,
+        },
+        [_, version, "bevy", bevy] => ScriptHeader {
+            version: version.parse().map_err(|_| error())?,
+            bevy: Some(bevy.to_string()),
+        },
+        _ => return Err(error()),
+    };
+
+    header.check_supported()?;
+    if header.version < FIRST_TEXT_VERSION {
+        return Err(format!(
+            "text scripts start at version {}, version {} can only be json",
+            FIRST_TEXT_VERSION, header.version
+        ));
+    }
+    Ok(header)
+}
+
+// Playback goes through the inputs in order, so an input out of place would hold up the ones after it
+pub(super) fn check_order(previous: &Timestamp, next: &Timestamp) -> Result<(), String> {
+    match (previous, next) {
+        (Timestamp::Time(previous), Timestamp::Time(next)) if next < previous => {
+            Err("timestamp is earlier than the one before it".to_string())
+        }
+        (Timestamp::Frame(previous), Timestamp::Frame(next)) if next < previous => {
+            Err("frame is earlier than the one before it".to_string())
+        }
+        (Timestamp::Time(_), Timestamp::Frame(_)) | (Timestamp::Frame(_), Timestamp::Time(_)) => {
+            Err("a script can't mix times and frames".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn write_timestamp(timestamp: &Timestamp) -> String {
+    match timestamp {
+        Timestamp::Time(time) => {
+            // Full precision, but without trailing zeros past the milliseconds
+            let nanos = format!("{:09}", time.subsec_nanos());
+            format!("{}.{:0<3}s", time.as_secs(), nanos.trim_end_matches('0'))
+        }
+        Timestamp::Frame(frame) => format!("{}f", frame),
+    }
+}
+
+pub(super) fn parse_timestamp(token: &str) -> Result<Timestamp, String> {
+    let error = || {
+        format!(
+            "invalid timestamp `{}`, expected seconds like `1.250s` or a frame like `75f`",
+            token
+        )
+    };
+
+    if let Some(frame) = token.strip_suffix('f') {
+        return frame.parse().map(Timestamp::Frame).map_err(|_| error());
+    }
+
+    let seconds = token.strip_suffix('s').ok_or_else(error)?;
+    let (secs, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    if fraction.len() > 9 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(error());
+    }
+
+    let secs = secs.parse().map_err(|_| error())?;
+    let nanos = format!("{:0<9}", fraction).parse().map_err(|_| error())?;
+    Ok(Timestamp::Time(Duration::new(secs, nanos)))
+}
+
+pub(super) fn write_input(input: &UserInput) -> String {
+    match input {
+        UserInput::KeyPress(key) => format!("press {}", id_token(key)),
+        UserInput::KeyRelese(key) => format!("release {}", id_token(key)),
+        UserInput::LogicalKeyPress(key, logical_key) => {
+            format!("press {} {}", id_token(key), key_token(logical_key))
+        }
+        UserInput::LogicalKeyRelease(key, logical_key) => {
+            format!("release {} {}", id_token(key), key_token(logical_key))
+        }
+        UserInput::MouseButtonPress(button) => format!("mouse-press {}", id_token(button)),
+        UserInput::MouseButtonRelease(button) => format!("mouse-release {}", id_token(button)),
+        UserInput::MouseScroll(scroll) => {
+            format!(
+                "scroll {} {} {}",
+                id_token(&scroll.unit),
+                scroll.x,
+                scroll.y
+            )
+        }
+        UserInput::MouseMove(delta, Some(position)) => {
+            format!("move {} {} {} {}", delta.x, delta.y, position.x, position.y)
+        }
+        UserInput::MouseMove(delta, None) => format!("move {} {}", delta.x, delta.y),
+        UserInput::Character(char) => format!("char {}", quoted(char)),
+        UserInput::ImePreedit(value, Some((start, end))) => {
+            format!("ime-preedit {} {} {}", quoted(value), start, end)
+        }
+        UserInput::ImePreedit(value, None) => format!("ime-preedit {}", quoted(value)),
+        UserInput::ImeCommit(value) => format!("ime-commit {}", quoted(value)),
+        UserInput::ImeEnabled => "ime-enabled".to_string(),
+        UserInput::ImeDisabled => "ime-disabled".to_string(),
+        UserInput::Touch(phase, id, position, force) => {
+            let mut line = format!(
+                "touch {} {} {} {}",
+                id_token(phase),
+                id,
+                position.x,
+                position.y
+            );
+            if let Some(force) = force {
+                line = format!("{} {}", line, token(force));
+            }
+            line
+        }
+        UserInput::ControllerConnect(gamepad, name) => {
+            format!("pad-connect {} {}", gamepad.id, quoted(name))
+        }
+        UserInput::ControllerDisconnect(gamepad) => format!("pad-disconnect {}", gamepad.id),
+        UserInput::ControllerButtonPress(button) => format!(
+            "pad-press {} {}",
+            button.gamepad.id,
+            id_token(&button.button_type)
+        ),
+        UserInput::ControllerButtonRelease(button) => format!(
+            "pad-release {} {}",
+            button.gamepad.id,
+            id_token(&button.button_type)
+        ),
+        UserInput::ControllerButtonChange(button, value) => format!(
+            "pad-button {} {} {}",
+            button.gamepad.id,
+            id_token(&button.button_type),
+            value
+        ),
+        UserInput::ControllerAxisChange(axis, value) => format!(
+            "pad-axis {} {} {}",
+            axis.gamepad.id,
+            id_token(&axis.axis_type),
+            value
+        ),
+        UserInput::Marker(name) => format!("mark {}", quoted(name)),
+        UserInput::Quit => "quit".to_string(),
+    }
+}
+
+fn parse_line(line: &str) -> Result<(Timestamp, UserInput), String> {
+    let mut tokens = tokenize(line)?.into_iter();
+    // The line isn't empty, so there is at least one token
+    let timestamp = parse_timestamp(tokens.next().unwrap())?;
+    let verb = tokens
+        .next()
+        .ok_or_else(|| "missing input after the timestamp".to_string())?;
+    let mut args = Args { tokens };
+
+    let input = match verb {
+        "press" | "release" => {
+            let key = args.id("a key code")?;
+            let logical_key = args.logical_key()?;
+            match (verb, logical_key) {
+                ("press", Some(logical_key)) => UserInput::LogicalKeyPress(key, logical_key),
+                ("press", None) => UserInput::KeyPress(key),
+                (_, Some(logical_key)) => UserInput::LogicalKeyRelease(key, logical_key),
+                (_, None) => UserInput::KeyRelese(key),
+            }
+        }
+        "mouse-press" => UserInput::MouseButtonPress(args.id("a mouse button")?),
+        "mouse-release" => UserInput::MouseButtonRelease(args.id("a mouse button")?),
+        "scroll" => UserInput::MouseScroll(MouseWheel {
+            unit: args.id("a scroll unit")?,
+            x: args.next("a number")?,
+            y: args.next("a number")?,
+            // Playback sends it to the primary window
+            window: Entity::PLACEHOLDER,
+        }),
+        "move" => {
+            let delta = Vec2::new(args.next("a number")?, args.next("a number")?);
+            let position = match args.optional("a number")? {
+                Some(x) => Some(Vec2::new(x, args.next("a number")?)),
+                None => None,
+            };
+            UserInput::MouseMove(delta, position)
+        }
+        "char" => UserInput::Character(args.next("a string")?),
+        "ime-preedit" => {
+            let value = args.next("a string")?;
+            let cursor = match args.optional("a number")? {
+                Some(start) => Some((start, args.next("a number")?)),
+                None => None,
+            };
+            UserInput::ImePreedit(value, cursor)
+        }
+        "ime-commit" => UserInput::ImeCommit(args.next("a string")?),
+        "ime-enabled" => UserInput::ImeEnabled,
+        "ime-disabled" => UserInput::ImeDisabled,
+        "touch" => UserInput::Touch(
+            args.id("a touch phase")?,
+            args.next("a touch id")?,
+            Vec2::new(args.next("a number")?, args.next("a number")?),
+            args.optional("a touch force")?,
+        ),
+        "pad-connect" => UserInput::ControllerConnect(
+            Gamepad::new(args.next("a gamepad id")?),
+            args.next("a string")?,
+        ),
+        "pad-disconnect" => {
+            UserInput::ControllerDisconnect(Gamepad::new(args.next("a gamepad id")?))
+        }
+        "pad-press" => UserInput::ControllerButtonPress(args.gamepad_button()?),
+        "pad-release" => UserInput::ControllerButtonRelease(args.gamepad_button()?),
+        "pad-button" => {
+            UserInput::ControllerButtonChange(args.gamepad_button()?, args.next("a number")?)
+        }
+        "pad-axis" => UserInput::ControllerAxisChange(
+            GamepadAxis::new(
+                Gamepad::new(args.next("a gamepad id")?),
+                args.id("a gamepad axis")?,
+            ),
+            args.next("a number")?,
+        ),
+        "mark" => UserInput::Marker(args.next("a marker name")?),
+        "quit" => UserInput::Quit,
+        _ => return Err(format!("unknown input `{}`", verb)),
+    };
+
+    args.finish()?;
+    Ok((timestamp, input))
+}
+
+// Splits on whitespace, except inside quotes and the brackets of inline json
+fn tokenize(line: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = vec![];
+    let mut start = None;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, char) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if char == '\\' {
+                escaped = true;
+            } else if char == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match char {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' if depth == 0 => return Err(format!("unexpected `{}`", char)),
+            '}' | ']' => depth -= 1,
+            _ => {}
+        }
+
+        if char.is_whitespace() && depth == 0 {
+            if let Some(start) = start.take() {
+                tokens.push(&line[start..index]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+
+    if in_string {
+        return Err("unterminated string".to_string());
+    }
+
+    if depth != 0 {
+        return Err("unclosed bracket".to_string());
+    }
+
+    if let Some(start) = start {
+        tokens.push(&line[start..]);
+    }
+
+    Ok(tokens)
+}
+
+struct Args<'a> {
+    tokens: IntoIter<&'a str>,
+}
+
+impl Args<'_> {
+    fn next<T: DeserializeOwned>(&mut self, expected: &str) -> Result<T, String> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| format!("missing {}", expected))?;
+        parse_token(token).ok_or_else(|| format!("expected {}, got `{}`", expected, token))
+    }
+
+    fn id<T: StableId>(&mut self, expected: &str) -> Result<T, String> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| format!("missing {}", expected))?;
+        T::from_id(token)
+            .or_else(|| parse_token(token))
+            .ok_or_else(|| format!("expected {}, got `{}`", expected, token))
+    }
+
+    fn optional<T: DeserializeOwned>(&mut self, expected: &str) -> Result<Option<T>, String> {
+        if self.tokens.as_slice().is_empty() {
+            Ok(None)
+        } else {
+            self.next(expected).map(Some)
+        }
+    }
+
+    fn logical_key(&mut self) -> Result<Option<Key>, String> {
+        let Some(token) = self.tokens.next() else {
+            return Ok(None);
+        };
+
+        let key = if token.starts_with('"') {
+            parse_token::<String>(token).map(|char| Key::Character(char.into()))
+        } else {
+            Key::from_id(token).or_else(|| parse_token(token))
+        };
+
+        key.map(Some).ok_or_else(|| {
+            format!(
+                "expected a logical key, got `{}` (characters go in quotes, like \"a\")",
+                token
+            )
+        })
+    }
+
+    fn gamepad_button(&mut self) -> Result<GamepadButton, String> {
+        Ok(GamepadButton::new(
+            Gamepad::new(self.next("a gamepad id")?),
+            self.id("a gamepad button")?,
+        ))
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        match self.tokens.next() {
+            Some(token) => Err(format!("unexpected `{}`", token)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Tokens are json, but plain words don't need quotes
+fn parse_token<T: DeserializeOwned>(token: &str) -> Option<T> {
+    serde_json::from_str(token)
+        .or_else(|_| serde_json::from_value(Value::String(token.to_string())))
+        .ok()
+}
+
+fn token<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value).unwrap() {
+        Value::String(word) if is_plain_word(&word) => word,
+        value => value.to_string(),
+    }
+}
+
+fn is_plain_word(word: &str) -> bool {
+    !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && serde_json::from_str::<Value>(word).is_err()
+}
+
+fn id_token<T: StableId>(value: &T) -> String {
+    value.id().unwrap_or_else(|| token(value))
+}
+
+fn key_token(key: &Key) -> String {
+    match key {
+        Key::Character(char) => quoted(char),
+        key => id_token(key),
+    }
+}
+
+fn quoted(text: &str) -> String {
+    Value::String(text.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> ParseError {
+        from_text(text).expect_err("the script shouldn't parse")
+    }
+
+    #[test]
+    fn round_trips_every_input() {
+        let script = TestScript::example();
+        let (header, parsed) = from_text(&to_text(&script)).unwrap();
+
+        assert_eq!(header.version, SCRIPT_VERSION);
+        assert_eq!(header.bevy.as_deref(), Some(BEVY_VERSION));
+        assert_eq!(parsed.events, script.events);
+        assert!(!parsed.expect_failure);
+    }
+
+    #[test]
+    fn round_trips_frames_and_expected_failures() {
+        let mut script = TestScript::example().with_frames();
+        script.expect_failure = true;
+        let text = to_text(&script);
+        let (_, parsed) = from_text(&text).unwrap();
+
+        assert!(text.contains("\n3f press KeyA \"a\"\n"));
+        assert_eq!(parsed.events, script.events);
+        assert!(parsed.expect_failure);
+    }
+
+    #[test]
+    fn quotes_strings_and_characters() {
+        let line = |input| write_input(&input);
+
+        assert_eq!(
+            line(UserInput::Character("say \"hi\" \\ bye".to_string())),
+            r#"char "say \"hi\" \\ bye""#
+        );
+        assert_eq!(
+            line(UserInput::LogicalKeyPress(
+                KeyCode::Quote,
+                Key::Character("\"".into())
+            )),
+            r#"press Quote "\"""#
+        );
+        assert_eq!(
+            line(UserInput::LogicalKeyPress(KeyCode::Space, Key::Space)),
+            "press Space Space"
+        );
+        assert_eq!(
+            parse_line(r#"0.5s press KeyA "a""#).unwrap().1,
+            UserInput::LogicalKeyPress(KeyCode::KeyA, Key::Character("a".into()))
+        );
+        assert_eq!(
+            parse_line("1s mark \"two  spaces\"").unwrap().1,
+            UserInput::Marker("two  spaces".to_string())
+        );
+    }
+
+    #[test]
+    fn timestamps_keep_every_digit() {
+        let cases = [
+            (Timestamp::Time(Duration::ZERO), "0.000s"),
+            (Timestamp::Time(Duration::from_millis(500)), "0.500s"),
+            (
+                Timestamp::Time(Duration::new(1, 250_000_001)),
+                "1.250000001s",
+            ),
+            (Timestamp::Time(Duration::from_secs(75)), "75.000s"),
+            (Timestamp::Frame(0), "0f"),
+            (Timestamp::Frame(75), "75f"),
+        ];
+
+        for (timestamp, text) in cases {
+            assert_eq!(write_timestamp(&timestamp), text);
+            assert_eq!(parse_timestamp(text), Ok(timestamp));
+        }
+        assert_eq!(
+            parse_timestamp("2s"),
+            Ok(Timestamp::Time(Duration::from_secs(2)))
+        );
+        assert!(parse_timestamp("1.0000000001s").is_err());
+        assert!(parse_timestamp("1.5").is_err());
+        assert!(parse_timestamp("-1f").is_err());
+    }
+
+    #[test]
+    fn reads_scripts_without_a_header() {
+        let (header, script) = from_text("# a comment\n\n  0.100s char a\n0.200s quit\n").unwrap();
+
+        assert_eq!(
+            script.events,
+            [
+                (
+                    Timestamp::Time(Duration::from_millis(100)),
+                    UserInput::Character("a".to_string())
+                ),
+                (Timestamp::Time(Duration::from_millis(200)), UserInput::Quit),
+            ]
+        );
+        assert_eq!(header.version, HEADERLESS_VERSION);
+        assert_eq!(header.bevy, None);
+    }
+
+    #[test]
+    fn errors_carry_the_line_number() {
+        let text = "bitt-script 2 bevy 0.13\n# comment\n\n0.100s press KeyA \"a\"\n0.200s jump\n";
+        let error = parse_error(text);
+        assert_eq!(error.line, 5);
+        assert_eq!(error.to_string(), "line 5: unknown input `jump`");
+
+        let cases = [
+            ("0.100s char \"open\n", 1, "unterminated string"),
+            (
+                "0.100s quit\n0.050s quit\n",
+                2,
+                "earlier than the one before it",
+            ),
+            ("1f quit\n0.500s quit\n", 2, "can't mix times and frames"),
+            ("0.100s press NotAKey\n", 1, "expected a key code"),
+            ("0.100s press KeyA a\n", 1, "characters go in quotes"),
+            ("0.100s quit now\n", 1, "unexpected `now`"),
+            ("\n\n0.100s move 1\n", 3, "missing a number"),
+            ("\n\nbitt-script 1\n", 3, "can only be json"),
+            ("bitt-script 99\n", 1, "unsupported script version 99"),
+            ("bitt-script two\n", 1, "invalid header"),
+        ];
+        for (text, line, message) in cases {
+            let error = parse_error(text);
+            assert_eq!(error.line, line, "{:?}", text);
+            assert!(
+                error.message.contains(message),
+                "{:?} gave `{}`",
+                text,
+                error.message
+            );
+        }
+    }
+}
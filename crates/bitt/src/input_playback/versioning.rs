@@ -0,0 +1,357 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::TestScript;
+
+/// Bumped whenever the way scripts are saved changes. Older scripts are migrated when they are loaded.
+pub(super) const SCRIPT_VERSION: u32 = 3;
+
+/// The version the text format was added in, there are no text scripts older than this.
+pub(super) const FIRST_TEXT_VERSION: u32 = 2;
+
+/// The bevy version bitt is built against, keep in sync with the dependency.
+pub(super) const BEVY_VERSION: &str = "0.13";
+
+/// What a script was saved with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct ScriptHeader {
+    pub(super) version: u32,
+    // Scripts from before versioning don't know this
+    pub(super) bevy: Option<String>,
+}
+
+impl ScriptHeader {
+    pub(super) fn current() -> Self {
+        Self {
+            version: SCRIPT_VERSION,
+            bevy: Some(BEVY_VERSION.to_string()),
+        }
+    }
+
+    pub(super) fn check_supported(&self) -> Result<(), String> {
+        if self.version == 0 || self.version > SCRIPT_VERSION {
+            Err(format!(
+                "unsupported script version {}, this version of bitt supports up to {}",
+                self.version, SCRIPT_VERSION
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A script as it is saved in json.
+#[derive(Serialize)]
+pub(super) struct VersionedScript<'a> {
+    #[serde(flatten)]
+    pub(super) header: ScriptHeader,
+    #[serde(flatten)]
+    pub(super) script: &'a TestScript,
+}
+
+/// Migrates a json script and decodes it.
+pub(super) fn load(mut script: Value) -> Result<(ScriptHeader, TestScript), String> {
+    let header = migrate(&mut script)?;
+    let script = serde_json::from_value(script).map_err(|err| err.to_string())?;
+    Ok((header, script))
+}
+
+// Each one upgrades a script from the version matching its index + 1 to the next one
+const MIGRATIONS: [fn(&mut Value); SCRIPT_VERSION as usize - 1] =
+    [rename_bevy_0_12_key_codes, unwrap_gamepad_ids];
+
+// Text scripts are parsed straight into inputs, so they are migrated line by line before that instead.
+// Each one upgrades a line from the version matching its index + `FIRST_TEXT_VERSION` to the next one.
+const TEXT_MIGRATIONS: [fn(&str) -> String; (SCRIPT_VERSION - FIRST_TEXT_VERSION) as usize] =
+    [str::to_string];
+
+/// Upgrades a line of a text script to the current version.
+pub(super) fn migrate_text_line(version: u32, line: &str) -> String {
+    TEXT_MIGRATIONS[(version - FIRST_TEXT_VERSION) as usize..]
+        .iter()
+        .fold(line.to_string(), |line, migration| migration(&line))
+}
+
+/// Upgrades a json script to the current version, returns the header it was saved with.
+fn migrate(script: &mut Value) -> Result<ScriptHeader, String> {
+    // Scripts from before versioning are version 1
+    let header = ScriptHeader {
+        version: script
+            .get("version")
+            .and_then(Value::as_u64)
+            .map_or(1, |version| version as u32),
+        bevy: script
+            .get("bevy")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    };
+    header.check_supported()?;
+
+    for migration in &MIGRATIONS[header.version as usize - 1..] {
+        migration(script);
+    }
+
+    Ok(header)
+}
+
+// Version 1 scripts could have been recorded with bevy 0.12, which named key codes after the old winit ones.
+// Key codes without a counterpart in bevy 0.13 are left alone, and fail to load.
+fn rename_bevy_0_12_key_codes(script: &mut Value) {
+    let Some(events) = script.get_mut("events").and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    for event in events {
+        let Some(input) = event.get_mut(1) else {
+            continue;
+        };
+
+        for variant in ["KeyPress", "KeyRelese"] {
+            if let Some(Value::String(key)) = input.get_mut(variant) {
+                if let Some(renamed) = bevy_0_12_key_code(key) {
+                    *key = renamed.to_string();
+                }
+            }
+        }
+    }
+}
+
+// Pads used to be saved the way bevy serializes them, `{"id": 0}`, now they are just the id.
+// Text scripts always had the plain id.
+fn unwrap_gamepad_ids(script: &mut Value) {
+    let Some(events) = script.get_mut("events").and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    for event in events {
+        let Some(Value::Object(input)) = event.get_mut(1) else {
+            continue;
+        };
+
+        for (variant, fields) in input.iter_mut() {
+            let pad = match (variant.as_str(), fields) {
+                ("ControllerDisconnect", pad) => Some(pad),
+                ("ControllerConnect", Value::Array(fields)) => fields.get_mut(0),
+                ("ControllerButtonPress" | "ControllerButtonRelease", button) => {
+                    button.get_mut("gamepad")
+                }
+                ("ControllerButtonChange" | "ControllerAxisChange", Value::Array(fields)) => fields
+                    .get_mut(0)
+                    .and_then(|button_or_axis| button_or_axis.get_mut("gamepad")),
+                _ => None,
+            };
+
+            if let Some(pad) = pad {
+                if let Some(id) = pad.get("id").cloned() {
+                    *pad = id;
+                }
+            }
+        }
+    }
+}
+
+fn bevy_0_12_key_code(key: &str) -> Option<String> {
+    let renamed = match key {
+        "Back" => "Backspace",
+        "Return" => "Enter",
+        "Left" => "ArrowLeft",
+        "Up" => "ArrowUp",
+        "Right" => "ArrowRight",
+        "Down" => "ArrowDown",
+        "Snapshot" => "PrintScreen",
+        "Scroll" => "ScrollLock",
+        "Numlock" => "NumLock",
+        "NumpadEquals" => "NumpadEqual",
+        "Apostrophe" => "Quote",
+        "Apps" => "ContextMenu",
+        "Calculator" => "LaunchApp2",
+        "Capital" => "CapsLock",
+        "Equals" => "Equal",
+        "Grave" => "Backquote",
+        "Kana" => "KanaMode",
+        "Mail" => "LaunchMail",
+        "Mute" => "AudioVolumeMute",
+        "MyComputer" => "LaunchApp1",
+        "NavigateForward" | "WebForward" => "BrowserForward",
+        "NavigateBackward" | "WebBack" => "BrowserBack",
+        "NextTrack" => "MediaTrackNext",
+        "PrevTrack" => "MediaTrackPrevious",
+        "NoConvert" => "NonConvert",
+        "Oem102" => "IntlBackslash",
+        "PlayPause" => "MediaPlayPause",
+        "VolumeDown" => "AudioVolumeDown",
+        "VolumeUp" => "AudioVolumeUp",
+        "Wake" => "WakeUp",
+        "WebFavorites" => "BrowserFavorites",
+        "WebHome" => "BrowserHome",
+        "WebRefresh" => "BrowserRefresh",
+        "WebSearch" => "BrowserSearch",
+        "WebStop" => "BrowserStop",
+        "Yen" => "IntlYen",
+        // Letters and digits were just `A` and `Key1`
+        letter if letter.len() == 1 && letter.chars().all(|c| c.is_ascii_uppercase()) => {
+            return Some(format!("Key{}", letter));
+        }
+        digit if digit.len() == 4 && digit.starts_with("Key") => {
+            let digit = &digit[3..];
+            return digit
+                .chars()
+                .all(|c| c.is_ascii_digit())
+                .then(|| format!("Digit{}", digit));
+        }
+        _ => return None,
+    };
+
+    Some(renamed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        input::gamepad::{GamepadAxisType, GamepadButtonType},
+        prelude::{Gamepad, GamepadAxis, GamepadButton, KeyCode},
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::input_playback::{Timestamp, UserInput};
+
+    fn save(script: &TestScript) -> Value {
+        serde_json::to_value(VersionedScript {
+            header: ScriptHeader::current(),
+            script,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn json_round_trips_every_input() {
+        for script in [TestScript::example(), TestScript::example().with_frames()] {
+            let saved = save(&script);
+            assert_eq!(saved["version"], json!(SCRIPT_VERSION));
+            assert_eq!(saved["bevy"], json!(BEVY_VERSION));
+
+            // Through a string as well, to catch anything that only survives as a `Value`
+            let saved = serde_json::from_str(&saved.to_string()).unwrap();
+            let (header, loaded) = load(saved).unwrap();
+            assert_eq!(header.version, SCRIPT_VERSION);
+            assert_eq!(loaded.events, script.events);
+        }
+    }
+
+    #[test]
+    fn migrates_bevy_0_12_key_codes() {
+        let script = json!({
+            "events": [
+                [{ "secs": 0, "nanos": 100_000_000 }, { "KeyPress": "A" }],
+                [{ "secs": 0, "nanos": 200_000_000 }, { "KeyRelese": "A" }],
+                [{ "secs": 0, "nanos": 300_000_000 }, { "KeyPress": "Key1" }],
+                [{ "secs": 0, "nanos": 400_000_000 }, { "KeyPress": "Return" }],
+                [{ "secs": 0, "nanos": 500_000_000 }, { "KeyPress": "Space" }],
+            ]
+        });
+
+        let (header, script) = load(script).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.bevy, None);
+
+        let inputs: Vec<_> = script.events.into_iter().map(|(_, input)| input).collect();
+        assert_eq!(
+            inputs,
+            [
+                UserInput::KeyPress(KeyCode::KeyA),
+                UserInput::KeyRelese(KeyCode::KeyA),
+                UserInput::KeyPress(KeyCode::Digit1),
+                UserInput::KeyPress(KeyCode::Enter),
+                UserInput::KeyPress(KeyCode::Space),
+            ]
+        );
+    }
+
+    #[test]
+    fn migrates_gamepad_ids() {
+        let button = json!({ "gamepad": { "id": 1 }, "button_type": "South" });
+        let axis = json!({ "gamepad": { "id": 1 }, "axis_type": "LeftStickX" });
+        let script = json!({
+            "version": 2,
+            "events": [
+                [{ "secs": 0, "nanos": 1 }, { "ControllerConnect": [{ "id": 1 }, "Pad"] }],
+                [{ "secs": 0, "nanos": 2 }, { "ControllerButtonPress": button }],
+                [{ "secs": 0, "nanos": 3 }, { "ControllerButtonChange": [button, 0.5] }],
+                [{ "secs": 0, "nanos": 4 }, { "ControllerAxisChange": [axis, -0.5] }],
+                [{ "secs": 0, "nanos": 5 }, { "ControllerButtonRelease": button }],
+                [{ "secs": 0, "nanos": 6 }, { "ControllerDisconnect": { "id": 1 } }],
+            ]
+        });
+
+        let (header, script) = load(script).unwrap();
+        assert_eq!(header.version, 2);
+
+        let pad = Gamepad::new(1);
+        let button = GamepadButton::new(pad, GamepadButtonType::South);
+        let inputs: Vec<_> = script.events.into_iter().map(|(_, input)| input).collect();
+        assert_eq!(
+            inputs,
+            [
+                UserInput::ControllerConnect(pad, "Pad".to_string()),
+                UserInput::ControllerButtonPress(button),
+                UserInput::ControllerButtonChange(button, 0.5),
+                UserInput::ControllerAxisChange(
+                    GamepadAxis::new(pad, GamepadAxisType::LeftStickX),
+                    -0.5
+                ),
+                UserInput::ControllerButtonRelease(button),
+                UserInput::ControllerDisconnect(pad),
+            ]
+        );
+
+        let saved = save(&TestScript::example());
+        let connect = saved["events"][0][1].clone();
+        assert_eq!(
+            connect,
+            json!({ "ControllerConnect": [1, "Pad \"Pro\" 2"] })
+        );
+    }
+
+    #[test]
+    fn renames_only_old_key_codes() {
+        assert_eq!(bevy_0_12_key_code("A").as_deref(), Some("KeyA"));
+        assert_eq!(bevy_0_12_key_code("Key1").as_deref(), Some("Digit1"));
+        assert_eq!(bevy_0_12_key_code("Return").as_deref(), Some("Enter"));
+        assert_eq!(bevy_0_12_key_code("KeyA"), None);
+        assert_eq!(bevy_0_12_key_code("KeyZ"), None);
+        assert_eq!(bevy_0_12_key_code("Digit1"), None);
+        assert_eq!(bevy_0_12_key_code("a"), None);
+    }
+
+    #[test]
+    fn current_scripts_are_left_alone() {
+        let script = json!({
+            "version": SCRIPT_VERSION,
+            "events": [[{ "secs": 1, "nanos": 0 }, { "KeyPress": "Enter" }]]
+        });
+
+        let (_, script) = load(script).unwrap();
+        assert_eq!(
+            script.events,
+            [(
+                Timestamp::Time(Duration::from_secs(1)),
+                UserInput::KeyPress(KeyCode::Enter)
+            )]
+        );
+        assert_eq!(
+            migrate_text_line(SCRIPT_VERSION, "1.000s press Enter"),
+            "1.000s press Enter"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [0, SCRIPT_VERSION + 1] {
+            let error = load(json!({ "version": version, "events": [] })).unwrap_err();
+            assert!(error.contains("unsupported script version"), "{}", error);
+        }
+    }
+}
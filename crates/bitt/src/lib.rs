@@ -4,6 +4,9 @@ mod test_wrangler;
 mod timeout_asserter_plugin;
 
 pub use headless_default_plugins::HeadlessDefaultPlugins;
-pub use input_playback::{PlaybackTestGear, PlaybackTestingOptions};
+pub use input_playback::{
+    convert_script, ExpectationAppExt, ExtendFrom, InputChange, InvariantAppExt, PlaybackTestGear,
+    PlaybackTestingOptions, RecordingControls, ScriptDiff, ScriptFormat, TestScript, Timestamp,
+};
 pub use test_wrangler::TestWrangler;
 pub use timeout_asserter_plugin::TimeoutAsserterPlugin;
@@ -0,0 +1,339 @@
+use std::{
+    fs::read_dir,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use bitt::{ScriptFormat, TestScript, Timestamp};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/// Inspects and edits the test scripts in bitt/test_scripts. Run it where the game is run from.
+///
+/// Timestamps are written like `1.250s`, or like `75f` for frame indexed scripts.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the inputs of a script with when they happen
+    Timeline {
+        /// Name of the test script
+        case: String,
+    },
+    /// Drops the inputs from the start and / or the end of a script
+    Trim {
+        /// Name of the test script
+        case: String,
+        /// Drop the inputs before this, the script then starts from here
+        #[arg(long)]
+        start: Option<Timestamp>,
+        /// Drop the inputs after this, the script then ends here
+        #[arg(long)]
+        end: Option<Timestamp>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Drops the inputs between two timestamps, and moves the ones after back to close the gap
+    Cut {
+        /// Name of the test script
+        case: String,
+        from: Timestamp,
+        to: Timestamp,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Moves inputs later, or earlier if the amount is negative, like `-0.5s`
+    Shift {
+        /// Name of the test script
+        case: String,
+        #[arg(allow_hyphen_values = true)]
+        by: String,
+        /// Only move the inputs from this point on
+        #[arg(long)]
+        from: Option<Timestamp>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Appends the inputs of one script to another
+    Concat {
+        /// Name of the test script that goes first
+        first: String,
+        /// Name of the test script that continues after the first one ends
+        second: String,
+        /// How long to wait between the scripts
+        #[arg(long)]
+        gap: Option<Timestamp>,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Saves a script in another format
+    Convert {
+        /// Name of the test script
+        case: String,
+        #[arg(value_enum)]
+        format: Format,
+        /// Save as this test script instead of overwriting the original
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Shows the inputs that were added, removed or moved between two scripts, fails if there are any
+    Diff {
+        /// Name of the older test script
+        old: String,
+        /// Name of the newer test script
+        new: String,
+        /// Ignore inputs that moved less than this, defaults to 0.100s or 6f
+        #[arg(long)]
+        tolerance: Option<Timestamp>,
+    },
+    /// Checks that scripts load and would play back properly
+    Validate {
+        /// Names of the test scripts, all of them if left out
+        cases: Vec<String>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Output {
+    /// Save as this test script instead of overwriting the original
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Format to save in, defaults to the format of the original
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Text,
+    Binary,
+}
+
+impl From<Format> for ScriptFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Json => ScriptFormat::Json,
+            Format::Text => ScriptFormat::Text,
+            Format::Binary => ScriptFormat::Binary,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Timeline { case } => {
+            let (script, _) = read(&case)?;
+            println!("{}", summary(&script));
+            print!("{}", script.timeline());
+        }
+        Command::Trim {
+            case,
+            start,
+            end,
+            output,
+        } => {
+            if start.is_none() && end.is_none() {
+                return Err("give --start, --end or both".to_string());
+            }
+
+            let (mut script, format) = read(&case)?;
+            // The end is in the same time as the start, so it goes first
+            if let Some(end) = end {
+                script.trim_end(end)?;
+            }
+            if let Some(start) = start {
+                script.trim_start(start)?;
+            }
+            save(&script, &case, format, output)?;
+        }
+        Command::Cut {
+            case,
+            from,
+            to,
+            output,
+        } => {
+            let (mut script, format) = read(&case)?;
+            script.cut(from, to)?;
+            save(&script, &case, format, output)?;
+        }
+        Command::Shift {
+            case,
+            by,
+            from,
+            output,
+        } => {
+            let (mut script, format) = read(&case)?;
+            let (earlier, by) = match by.strip_prefix('-') {
+                Some(by) => (true, by.parse::<Timestamp>()?),
+                None => (false, by.parse::<Timestamp>()?),
+            };
+
+            let from = from.unwrap_or(by.zero());
+            if earlier {
+                script.shift_earlier(from, by)?;
+            } else {
+                script.shift_later(from, by)?;
+            }
+            save(&script, &case, format, output)?;
+        }
+        Command::Concat {
+            first,
+            second,
+            gap,
+            output,
+        } => {
+            let (mut script, format) = read(&first)?;
+            let (other, _) = read(&second)?;
+            let gap = gap.unwrap_or(
+                script
+                    .end()
+                    .map_or(Timestamp::Time(Duration::ZERO), |end| end.zero()),
+            );
+            script.append(&other, gap)?;
+            save(&script, &first, format, output)?;
+        }
+        Command::Convert {
+            case,
+            format,
+            output,
+        } => {
+            let (script, _) = read(&case)?;
+            let output = Output {
+                output,
+                format: None,
+            };
+            save(&script, &case, format.into(), output)?;
+        }
+        Command::Diff {
+            old,
+            new,
+            tolerance,
+        } => {
+            let (old_script, _) = read(&old)?;
+            let (new_script, _) = read(&new)?;
+            let tolerance = tolerance.unwrap_or(match old_script.end() {
+                Some(Timestamp::Frame(_)) => Timestamp::Frame(6),
+                _ => Timestamp::Time(Duration::from_millis(100)),
+            });
+
+            let diff = old_script.diff(&new_script, tolerance)?;
+            if !diff.is_empty() {
+                print!("{}", diff);
+                return Err(format!("{} inputs differ", diff.changes.len()));
+            }
+            println!("{} and {} have the same inputs", old, new);
+        }
+        Command::Validate { cases } => {
+            let cases = if cases.is_empty() {
+                all_cases()?
+            } else {
+                cases
+            };
+
+            let mut failed = false;
+            for case in cases {
+                let problems = match read(&case) {
+                    Ok((script, _)) => {
+                        let problems = script.validate();
+                        if problems.is_empty() {
+                            println!("{}: ok, {}", case, summary(&script));
+                        }
+                        problems
+                    }
+                    Err(err) => vec![err],
+                };
+
+                for problem in &problems {
+                    println!("{}: {}", case, problem);
+                }
+                failed |= !problems.is_empty();
+            }
+
+            if failed {
+                return Err("some scripts have problems".to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Same place `PlaybackTestGear` looks in
+fn script_path(case: &str) -> PathBuf {
+    Path::new("bitt")
+        .join("test_scripts")
+        .join(format!("{}.bitt_script", case))
+}
+
+fn read(case: &str) -> Result<(TestScript, ScriptFormat), String> {
+    let path = script_path(case);
+    if !path.exists() {
+        return Err(format!("{} doesn't exist", path.display()));
+    }
+
+    TestScript::read(&path).map_err(|err| format!("failed to load {}: {}", path.display(), err))
+}
+
+fn save(
+    script: &TestScript,
+    case: &str,
+    format: ScriptFormat,
+    output: Output,
+) -> Result<(), String> {
+    let path = script_path(output.output.as_deref().unwrap_or(case));
+    let format = output.format.map_or(format, ScriptFormat::from);
+    script
+        .write(&path, format)
+        .map_err(|err| format!("failed to save {}: {}", path.display(), err))?;
+
+    println!("Saved {}, {}", path.display(), summary(script));
+    Ok(())
+}
+
+fn all_cases() -> Result<Vec<String>, String> {
+    let dir = Path::new("bitt").join("test_scripts");
+    let entries =
+        read_dir(&dir).map_err(|err| format!("failed to read {}: {}", dir.display(), err))?;
+
+    let mut cases: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? == "bitt_script" {
+                Some(path.file_stem()?.to_string_lossy().to_string())
+            } else {
+                None
+            }
+        })
+        .collect();
+    cases.sort();
+    Ok(cases)
+}
+
+fn summary(script: &TestScript) -> String {
+    let summary = match script.end() {
+        Some(end) => format!("{} inputs, ends at {}", script.len(), end),
+        None => "no inputs".to_string(),
+    };
+
+    if script.expects_failure() {
+        format!("{}, expected to fail", summary)
+    } else {
+        summary
+    }
+}
@@ -1,10 +1,27 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
 
 /// A resource that can be used to state the outcome of this test.
 #[derive(Resource, Debug, Default)]
 pub struct TestWrangler {
     pub(crate) outcome: Option<bool>,
+    pub(crate) reason: Option<String>,
     pub(crate) started: bool,
+    // Only a test gear consumes marks, so without one they aren't kept
+    pub(crate) collect_marks: bool,
+    pub(crate) marks: Vec<String>,
+    pub(crate) assertions: Vec<Assertion>,
+}
+
+/// One of the things a test checks, see `TestWrangler::expect`.
+#[derive(Debug, Clone)]
+pub(crate) struct Assertion {
+    pub(crate) name: String,
+    pub(crate) outcome: Option<bool>,
+    pub(crate) reason: Option<String>,
+    // Filled in by the test gear, counted from the start of the test
+    pub(crate) resolved_at: Option<Duration>,
 }
 
 impl TestWrangler {
@@ -15,19 +32,266 @@ impl TestWrangler {
         self.started = true;
     }
 
+    /// Marks a checkpoint in the test, like `mark("boss_spawned")`.
+    /// While recording, the marker is saved in the script. During playback, the same markers have to be reached in the
+    /// same order and at about the same time as in the recording, or the test fails.
+    /// Does nothing without a `PlaybackTestGear`.
+    pub fn mark(&mut self, name: impl Into<String>) {
+        if self.collect_marks {
+            self.marks.push(name.into());
+        }
+    }
+
     /// Marks the current test as passed.
     /// Once a test is marked as failed or passed, it cannot be changed.
+    /// Does nothing while any assertion added with `expect` is pending, as the test only passes once all of them have.
     pub fn pass(&mut self) {
-        if self.outcome.is_none() {
-            self.outcome = Some(true);
-        }
+        self.pass_unless_pending(None);
+    }
+
+    /// Marks the current test as passed, with a note that is printed and saved in the artefacts.
+    /// Once a test is marked as failed or passed, it cannot be changed.
+    /// Does nothing while any assertion added with `expect` is pending, as the test only passes once all of them have.
+    pub fn pass_with(&mut self, note: impl Into<String>) {
+        self.pass_unless_pending(Some(note.into()));
     }
 
     /// Marks the current test as failed.
     /// Once a test is marked as failed or passed, it cannot be changed.
     pub fn fail(&mut self) {
+        self.resolve(false, None);
+    }
+
+    /// Marks the current test as failed, with a reason that is printed and saved in the artefacts.
+    /// Once a test is marked as failed or passed, it cannot be changed.
+    pub fn fail_with(&mut self, reason: impl Into<String>) {
+        self.resolve(false, Some(reason.into()));
+    }
+
+    /// Adds a named assertion, like `expect("score reached 2")`, that has to pass for the test to pass.
+    /// Once there are assertions, the test passes when all of them have passed, and fails as soon as one fails.
+    /// An assertion added after the test passed takes the pass back, until it has passed too.
+    /// Adding the same name twice does nothing.
+    pub fn expect(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if self
+            .assertions
+            .iter()
+            .any(|assertion| assertion.name == name)
+        {
+            return;
+        }
+
+        self.assertions.push(Assertion {
+            name,
+            outcome: None,
+            reason: None,
+            resolved_at: None,
+        });
+        if self.outcome == Some(true) {
+            self.outcome = None;
+            self.reason = None;
+        }
+    }
+
+    /// Marks a named assertion as passed.
+    /// The name has to be added with `expect` first, otherwise this only logs a warning.
+    /// Once an assertion is marked as failed or passed, it cannot be changed.
+    pub fn pass_assertion(&mut self, name: impl Into<String>) {
+        self.resolve_assertion(name.into(), true, None);
+    }
+
+    /// Marks a named assertion as failed with a reason, which fails the test.
+    /// A name that wasn't added with `expect` is added, so that the failure shows up in the artefacts.
+    /// Once an assertion is marked as failed or passed, it cannot be changed.
+    pub fn fail_assertion(&mut self, name: impl Into<String>, reason: impl Into<String>) {
+        self.resolve_assertion(name.into(), false, Some(reason.into()));
+    }
+
+    fn resolve_assertion(&mut self, name: String, passed: bool, reason: Option<String>) {
+        if !passed {
+            self.expect(name.clone());
+        }
+        let Some(assertion) = self
+            .assertions
+            .iter_mut()
+            .find(|assertion| assertion.name == name)
+        else {
+            warn!(
+                "`{}` was passed, but it isn't an assertion. Add it with `expect` first.",
+                name
+            );
+            return;
+        };
+        if assertion.outcome.is_some() {
+            return;
+        }
+        assertion.outcome = Some(passed);
+        assertion.reason = reason;
+
+        if !passed {
+            let reason = match &assertion.reason {
+                Some(reason) => format!("`{}` failed, {}", name, reason),
+                None => format!("`{}` failed", name),
+            };
+            self.resolve(false, Some(reason));
+        } else if self
+            .assertions
+            .iter()
+            .all(|assertion| assertion.outcome == Some(true))
+        {
+            let count = self.assertions.len();
+            self.resolve(true, Some(format!("all {} assertions passed", count)));
+        }
+    }
+
+    fn pass_unless_pending(&mut self, note: Option<String>) {
+        if self
+            .assertions
+            .iter()
+            .any(|assertion| assertion.outcome.is_none())
+        {
+            return;
+        }
+        self.resolve(true, note);
+    }
+
+    /// Forgets the outcome of the test and of its assertions, but keeps the assertions that were added.
+    pub(crate) fn reset_outcome(&mut self) {
+        self.outcome = None;
+        self.reason = None;
+        for assertion in &mut self.assertions {
+            assertion.outcome = None;
+            assertion.reason = None;
+            assertion.resolved_at = None;
+        }
+    }
+
+    fn resolve(&mut self, passed: bool, reason: Option<String>) {
         if self.outcome.is_none() {
-            self.outcome = Some(false);
+            self.outcome = Some(passed);
+            self.reason = reason;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(wrangler: &TestWrangler) -> (Option<bool>, Option<&str>) {
+        (wrangler.outcome, wrangler.reason.as_deref())
+    }
+
+    #[test]
+    fn pass_and_fail_are_final() {
+        let mut passed = TestWrangler::default();
+        passed.pass_with("score reached 2");
+        passed.fail();
+        assert_eq!(outcome(&passed), (Some(true), Some("score reached 2")));
+
+        let mut failed = TestWrangler::default();
+        failed.fail_with("fell off the map");
+        failed.pass();
+        assert_eq!(outcome(&failed), (Some(false), Some("fell off the map")));
+    }
+
+    #[test]
+    fn passes_once_every_assertion_has() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.expect("door opened");
+        wrangler.expect("boss spawned");
+        wrangler.expect("door opened");
+        assert_eq!(wrangler.assertions.len(), 2);
+
+        wrangler.pass_assertion("door opened");
+        wrangler.pass_assertion("not expected");
+        assert_eq!(outcome(&wrangler), (None, None));
+
+        wrangler.pass_assertion("boss spawned");
+        assert_eq!(
+            outcome(&wrangler),
+            (Some(true), Some("all 2 assertions passed"))
+        );
+    }
+
+    #[test]
+    fn fails_as_soon_as_an_assertion_does() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.expect("door opened");
+        wrangler.fail_assertion("boss spawned", "it never did");
+        assert_eq!(
+            outcome(&wrangler),
+            (Some(false), Some("`boss spawned` failed, it never did"))
+        );
+        assert_eq!(wrangler.assertions.len(), 2);
+
+        wrangler.pass_assertion("door opened");
+        assert_eq!(wrangler.outcome, Some(false));
+    }
+
+    #[test]
+    fn pass_waits_for_pending_assertions() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.expect("door opened");
+        wrangler.pass();
+        assert_eq!(wrangler.outcome, None);
+
+        wrangler.pass_assertion("door opened");
+        wrangler.pass();
+        assert_eq!(wrangler.outcome, Some(true));
+    }
+
+    #[test]
+    fn a_later_assertion_takes_the_pass_back() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.expect("door opened");
+        wrangler.pass_assertion("door opened");
+        assert_eq!(wrangler.outcome, Some(true));
+
+        wrangler.expect("boss spawned");
+        assert_eq!(outcome(&wrangler), (None, None));
+
+        wrangler.pass_assertion("boss spawned");
+        assert_eq!(
+            outcome(&wrangler),
+            (Some(true), Some("all 2 assertions passed"))
+        );
+
+        let mut failed = TestWrangler::default();
+        failed.fail();
+        failed.expect("door opened");
+        assert_eq!(failed.outcome, Some(false));
+    }
+
+    #[test]
+    fn reset_keeps_the_assertions() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.expect("door opened");
+        wrangler.fail_assertion("door opened", "it was locked");
+        wrangler.assertions[0].resolved_at = Some(Duration::from_secs(1));
+
+        wrangler.reset_outcome();
+        assert_eq!(outcome(&wrangler), (None, None));
+        let assertion = &wrangler.assertions[0];
+        assert_eq!(assertion.name, "door opened");
+        assert_eq!(
+            (assertion.outcome, &assertion.reason, assertion.resolved_at),
+            (None, &None, None)
+        );
+
+        wrangler.pass_assertion("door opened");
+        assert_eq!(wrangler.outcome, Some(true));
+    }
+
+    #[test]
+    fn marks_are_only_kept_for_a_test_gear() {
+        let mut wrangler = TestWrangler::default();
+        wrangler.mark("door");
+        assert!(wrangler.marks.is_empty());
+
+        wrangler.collect_marks = true;
+        wrangler.mark("door");
+        assert_eq!(wrangler.marks, ["door"]);
+    }
+}
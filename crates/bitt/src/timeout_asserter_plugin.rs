@@ -1,8 +1,14 @@
-use std::time::Duration;
+use std::{
+    fs::{create_dir_all, write},
+    time::Duration,
+};
 
 use bevy::{app::AppExit, prelude::*};
 
-use crate::TestWrangler;
+use crate::{
+    input_playback::{ArtefactPaths, ResultWriter},
+    TestWrangler,
+};
 
 #[derive(Debug, Resource)]
 struct Timeout(Duration);
@@ -10,6 +16,7 @@ struct Timeout(Duration);
 /// A plugin that will add an Asserter and panic if it runs for longer than the given duration.
 ///
 /// Useful for cases when you want to test a combo of some systems in relative isolation.
+/// The outcome and its reason are printed, and saved in the artefacts if there is also a `PlaybackTestGear`.
 #[derive(Debug)]
 pub struct TimeoutAsserterPlugin(pub Duration);
 
@@ -23,17 +30,37 @@ impl Plugin for TimeoutAsserterPlugin {
 
 fn timeout(mut asserter: ResMut<TestWrangler>, time: Res<Time<Real>>, timeout: Res<Timeout>) {
     if time.elapsed() >= timeout.0 {
-        dbg!("Test timed out");
-        asserter.fail();
+        asserter.fail_with(format!("timed out after {:.1}s", timeout.0.as_secs_f32()));
     }
 }
 
-fn exit_on_asserter_result(asserter: Res<TestWrangler>, mut exit: EventWriter<AppExit>) {
-    if let Some(result) = asserter.outcome {
-        if result {
-            exit.send(AppExit);
-        } else {
-            panic!("Test failed");
-        }
+fn exit_on_asserter_result(
+    asserter: Res<TestWrangler>,
+    mut exit: EventWriter<AppExit>,
+    artefacts: Option<Res<ArtefactPaths>>,
+    result: ResultWriter,
+) {
+    let Some(passed) = asserter.outcome else {
+        return;
+    };
+
+    let outcome = if passed { "passed" } else { "failed" };
+    let message = match &asserter.reason {
+        Some(reason) => format!("{}: {}", outcome, reason),
+        None => outcome.to_string(),
+    };
+
+    if let Some(artefacts) = artefacts {
+        create_dir_all(&artefacts.base).unwrap();
+        write(artefacts.outcome(), format!("{}\n", message)).unwrap();
+    }
+
+    if passed {
+        println!("Test {}", message);
+        exit.send(AppExit);
+    } else {
+        // The test gear would write the result at the end of the frame, which the panic doesn't get to
+        result.write(false, asserter.reason.as_deref());
+        panic!("Test {}", message);
     }
 }
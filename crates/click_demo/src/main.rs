@@ -1,5 +1,7 @@
+use std::env;
+
 use bevy::prelude::*;
-use bitt::{PlaybackTestGear, PlaybackTestingOptions, TestWrangler};
+use bitt::{HeadlessDefaultPlugins, PlaybackTestGear, PlaybackTestingOptions, TestWrangler};
 use clap::{Parser, ValueEnum};
 use click_demo::{ClickDemoPlugin, Points};
 
@@ -31,7 +33,13 @@ fn main() {
     let args = Args::parse();
     let mut app = App::new();
 
-    app.add_plugins(DefaultPlugins).add_plugins(ClickDemoPlugin);
+    if env::var("HEADLESS").is_ok() {
+        app.add_plugins(HeadlessDefaultPlugins);
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+
+    app.add_plugins(ClickDemoPlugin);
 
     match args.script {
         Some(script) => {